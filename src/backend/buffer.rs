@@ -1,9 +1,13 @@
+use super::undo::{self, Change, History};
+use crate::config;
 use ropey::Rope;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::Range;
 use std::path::Path;
-use std::{ffi::OsStr, path::PathBuf};
+use std::path::PathBuf;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Language {
@@ -36,22 +40,31 @@ pub enum Mode {
     Insert,
     Visual,
     Normal,
+    Command,
 }
 
 // Individual Buffer Struct
-pub struct Buffer<'a> {
-    pub p: Option<&'a Path>,
-    pub name: Option<&'a OsStr>,
+pub struct Buffer {
+    // Assigned by the Editor that owns the buffer
+    pub id: usize,
+    pub p: Option<PathBuf>,
+    pub name: Option<OsString>,
     pub lang: Language,
     pub lang_str: &'static str,
     pub line_count: usize,
     pub rope: Rope,
     pub mode: Mode,
+    pub history: History,
+    // (line, column, first shown line) when the buffer was last shown
+    pub last_cursor: (usize, usize, usize),
+    // Line numbers for `'x` addresses in commands
+    pub marks: HashMap<char, usize>,
 }
 
-impl<'a> Buffer<'a> {
-    pub fn new() -> Buffer<'a> {
+impl Buffer {
+    pub fn new() -> Buffer {
         Buffer {
+            id: 0,
             p: None,
             name: None,
             lang: Language::Txt,
@@ -59,23 +72,274 @@ impl<'a> Buffer<'a> {
             line_count: 0,
             rope: Rope::new(),
             mode: Mode::Normal,
+            history: History::new(),
+            last_cursor: (1, 1, 0),
+            marks: HashMap::new(),
         }
     }
 
-    pub fn set_mode(mut self, m: Mode) -> Buffer<'a> {
+    pub fn set_mode(mut self, m: Mode) -> Buffer {
         self.mode = m;
         self
     }
 
-    pub fn set_path(mut self, p: &'a Path) -> Buffer<'a> {
-        self.name = p.file_name();
-        self.lang = derive_file_type(p);
-        self.lang_str = derive_file_str(p);
-        self.rope = Rope::from_reader(BufReader::new(File::open(&p).unwrap())).unwrap();
+    // Start out with `text`, not as an undoable change
+    pub fn set_text(mut self, text: &str) -> Buffer {
+        self.rope = Rope::from_str(text);
+        self.line_count = self.rope.len_lines();
+        self.history.mark_saved();
+        self
+    }
+
+    pub fn set_path(mut self, p: PathBuf) -> Buffer {
+        self.name = p.file_name().map(|n| n.to_os_string());
+        self.lang = derive_file_type(&p);
+        self.lang_str = derive_file_str(&p);
+        // A file that doesn't exist yet starts out empty
+        self.rope = match File::open(&p) {
+            Ok(f) => Rope::from_reader(BufReader::new(f)).unwrap(),
+            Err(_) => Rope::new(),
+        };
         self.line_count = self.rope.len_lines();
         self.p = Some(p);
+        self.load_undo_file();
         self
     }
+
+    // Has the rope changed since it was last read or written
+    // Undoing back to the saved text counts as unmodified
+    pub fn is_modified(&self) -> bool {
+        !self.history.is_saved()
+    }
+
+    // Write the rope to disk
+    // Goes through a temp file so a failed write never truncates the original
+    pub fn save(&mut self, backup: bool) -> io::Result<()> {
+        let p = match &self.p {
+            Some(p) => p,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no file name")),
+        };
+
+        // Write through symlinks instead of replacing them
+        let target = fs::canonicalize(p).unwrap_or_else(|_| p.clone());
+        let perms = fs::metadata(&target).ok().map(|m| m.permissions());
+
+        if backup && target.is_file() {
+            let mut backup_path = target.clone().into_os_string();
+            backup_path.push("~");
+            fs::copy(&target, backup_path)?;
+        }
+
+        let mut tmp_name = OsStr::new(".").to_os_string();
+        tmp_name.push(target.file_name().unwrap_or_else(|| OsStr::new("zed")));
+        tmp_name.push(".zed-tmp");
+        let tmp = target.with_file_name(tmp_name);
+
+        let written = self.write_to(&tmp).and_then(|_| {
+            if let Some(perms) = perms {
+                fs::set_permissions(&tmp, perms)?;
+            }
+            fs::rename(&tmp, &target)
+        });
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+            return written;
+        }
+
+        self.history.mark_saved();
+        // The file on disk now matches the history
+        let _ = self.write_undo_file();
+        Ok(())
+    }
+
+    // Give the buffer a new file name and save it there
+    pub fn save_as(&mut self, p: PathBuf, backup: bool) -> io::Result<()> {
+        self.name = p.file_name().map(|n| n.to_os_string());
+        self.lang = derive_file_type(&p);
+        self.lang_str = derive_file_str(&p);
+        self.p = Some(p);
+        self.save(backup)
+    }
+
+    fn write_to(&self, p: &Path) -> io::Result<()> {
+        let file = File::create(p)?;
+        let mut writer = BufWriter::new(&file);
+        self.rope.write_to(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        file.sync_all()
+    }
+
+    // Undo files live in the data dir, named after a hash of the file's path
+    fn undo_file(&self) -> Option<PathBuf> {
+        let p = self.p.as_ref()?;
+        let full = fs::canonicalize(p).unwrap_or_else(|_| p.clone());
+        let key = undo::hash_bytes(Some(full.to_string_lossy().as_bytes()));
+        Some(
+            config::data_dir()
+                .join("undo")
+                .join(format!("{:016x}", key)),
+        )
+    }
+
+    pub fn content_hash(&self) -> u64 {
+        undo::hash_bytes(self.rope.chunks().map(|c| c.as_bytes()))
+    }
+
+    // Restore history from a previous session, if the file is unchanged since
+    fn load_undo_file(&mut self) {
+        let data = match self.undo_file().and_then(|f| fs::read(f).ok()) {
+            Some(d) => d,
+            None => return,
+        };
+        if let Some((hash, history)) = History::deserialize(&data) {
+            if hash == self.content_hash() {
+                self.history = history;
+                self.history.mark_saved();
+            }
+        }
+    }
+
+    // Only while the rope matches the file, the undo file is keyed to
+    // the file's contents and one for unsaved text would never load
+    pub fn write_undo_file(&self) -> io::Result<()> {
+        if self.is_modified() {
+            return Ok(());
+        }
+        let file = match self.undo_file() {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        if self.history.undo.is_empty() && self.history.redo.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(file, self.history.serialize(self.content_hash()))
+    }
+
+    // Editing methods
+    // All positions are char indices into the rope
+    pub fn insert_char(&mut self, char_idx: usize, c: char) {
+        self.insert_str(char_idx, c.to_string().as_str());
+    }
+
+    pub fn insert_str(&mut self, char_idx: usize, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        self.history.record(Change::Insert {
+            at: char_idx,
+            text: s.to_string(),
+        });
+        self.raw_insert(char_idx, s);
+    }
+
+    pub fn delete(&mut self, range: Range<usize>) {
+        if range.start >= range.end || range.end > self.rope.len_chars() {
+            return;
+        }
+        self.history.record(Change::Delete {
+            at: range.start,
+            text: self.rope.slice(range.clone()).to_string(),
+        });
+        self.raw_delete(range);
+    }
+
+    // Edits that bypass the history, used when undoing
+    fn raw_insert(&mut self, char_idx: usize, s: &str) {
+        self.rope.insert(char_idx, s);
+        self.line_count = self.rope.len_lines();
+    }
+
+    fn raw_delete(&mut self, range: Range<usize>) {
+        self.rope.remove(range);
+        self.line_count = self.rope.len_lines();
+    }
+
+    // Icon part of lang_str, empty for languages without one
+    pub fn icon(&self) -> &'static str {
+        match self.lang_str.split_once(' ') {
+            Some((icon, _)) => icon,
+            None => "",
+        }
+    }
+
+    // Name shown to the user
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => String::from("[No Name]"),
+        }
+    }
+
+    // Group every edit until `commit` into one undo step
+    pub fn begin_transaction(&mut self, cursor: usize) {
+        self.history.begin(cursor);
+    }
+
+    pub fn commit_transaction(&mut self, cursor: usize) {
+        self.history.commit(cursor);
+    }
+
+    // Returns where the cursor should go
+    pub fn undo(&mut self) -> Option<usize> {
+        let t = self.history.pop_undo()?;
+        for change in t.changes.iter().rev() {
+            match change {
+                Change::Insert { at, text } => {
+                    self.raw_delete(*at..*at + text.chars().count());
+                }
+                Change::Delete { at, text } => self.raw_insert(*at, text),
+            }
+        }
+        Some(t.cursor_before)
+    }
+
+    pub fn redo(&mut self) -> Option<usize> {
+        let t = self.history.pop_redo()?;
+        for change in t.changes.iter() {
+            match change {
+                Change::Insert { at, text } => self.raw_insert(*at, text),
+                Change::Delete { at, text } => {
+                    self.raw_delete(*at..*at + text.chars().count());
+                }
+            }
+        }
+        Some(t.cursor_after)
+    }
+
+    // Break the line in two at `char_idx`
+    pub fn split_line(&mut self, char_idx: usize) {
+        self.insert_char(char_idx, '\n');
+    }
+
+    // Join `line` with the line below it
+    pub fn join_lines(&mut self, line: usize) {
+        if line + 1 >= self.line_count {
+            return;
+        }
+        let end = self.rope.line_to_char(line + 1);
+        let start = self.rope.line_to_char(line) + self.line_len(line);
+        self.delete(start..end);
+    }
+
+    // Number of chars on `line`, not counting the line ending
+    pub fn line_len(&self, line: usize) -> usize {
+        if line >= self.line_count {
+            return 0;
+        }
+        let l = self.rope.line(line);
+        let mut len = l.len_chars();
+        if len > 0 && l.char(len - 1) == '\n' {
+            len -= 1;
+            if len > 0 && l.char(len - 1) == '\r' {
+                len -= 1;
+            }
+        }
+        len
+    }
 }
 
 fn derive_file_str(p: &Path) -> &'static str {
@@ -141,3 +405,29 @@ fn derive_file_type(p: &Path) -> Language {
         Language::Txt
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modified_until_undone() {
+        let mut buf = Buffer::new().set_text("one\ntwo\n");
+        assert!(!buf.is_modified());
+
+        buf.insert_str(3, " more");
+        buf.join_lines(0);
+        assert!(buf.is_modified());
+        buf.undo();
+        assert!(buf.is_modified());
+        buf.undo();
+        assert!(!buf.is_modified());
+        buf.redo();
+        assert!(buf.is_modified());
+
+        // Same text by other means is still a change, like in vim
+        buf.delete(3..8);
+        assert_eq!(buf.rope.to_string(), "one\ntwo\n");
+        assert!(buf.is_modified());
+    }
+}
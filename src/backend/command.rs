@@ -0,0 +1,298 @@
+// Ex-style commands, like `:w`, `:e file` or `:10,20d`
+use super::buffer::Buffer;
+use crate::error::Error;
+
+// Where a line address starts from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Base {
+    Line(usize),
+    Current,
+    Last,
+    Mark(char),
+}
+
+// A line address like `10`, `.`, `$-2` or `'<`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub base: Base,
+    pub offset: isize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    pub start: Address,
+    pub end: Address,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub range: Option<Range>,
+    pub name: String,
+    // `:q!`
+    pub bang: bool,
+    pub args: String,
+}
+
+impl Command {
+    // Parse the text typed after `:`
+    pub fn parse(input: &str) -> Result<Command, Error> {
+        let chars: Vec<char> = input.trim().chars().collect();
+        let mut pos = 0;
+
+        let range = parse_range(&chars, &mut pos)?;
+
+        // Names are letters, or a single symbol like `&`
+        let start = pos;
+        while pos < chars.len() && chars[pos].is_ascii_alphabetic() {
+            pos += 1;
+        }
+        if pos == start && pos < chars.len() && !chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        let name: String = chars[start..pos].iter().collect();
+
+        let mut bang = false;
+        if pos < chars.len() && chars[pos] == '!' {
+            bang = true;
+            pos += 1;
+        }
+
+        let args: String = chars[pos..].iter().collect();
+
+        Ok(Command {
+            range,
+            name,
+            bang,
+            args: args.trim().to_string(),
+        })
+    }
+}
+
+fn parse_range(chars: &[char], pos: &mut usize) -> Result<Option<Range>, Error> {
+    if chars.get(*pos) == Some(&'%') {
+        *pos += 1;
+        return Ok(Some(Range {
+            start: Address {
+                base: Base::Line(1),
+                offset: 0,
+            },
+            end: Address {
+                base: Base::Last,
+                offset: 0,
+            },
+        }));
+    }
+
+    let start = match parse_address(chars, pos)? {
+        Some(a) => a,
+        None => return Ok(None),
+    };
+    let mut end = start.clone();
+    if chars.get(*pos) == Some(&',') {
+        *pos += 1;
+        end = match parse_address(chars, pos)? {
+            Some(a) => a,
+            None => return Err(Error::InvalidRange),
+        };
+    }
+    Ok(Some(Range { start, end }))
+}
+
+fn parse_address(chars: &[char], pos: &mut usize) -> Result<Option<Address>, Error> {
+    let base = match chars.get(*pos) {
+        Some(c) if c.is_ascii_digit() => Base::Line(parse_number(chars, pos)),
+        Some('.') => {
+            *pos += 1;
+            Base::Current
+        }
+        Some('$') => {
+            *pos += 1;
+            Base::Last
+        }
+        Some('\'') => match chars.get(*pos + 1) {
+            Some(m) => {
+                *pos += 2;
+                Base::Mark(*m)
+            }
+            None => return Err(Error::InvalidRange),
+        },
+        // `:+2` is relative to the cursor
+        Some('+') | Some('-') => Base::Current,
+        _ => return Ok(None),
+    };
+
+    let mut offset = 0;
+    while let Some(c) = chars.get(*pos) {
+        let sign = match c {
+            '+' => 1,
+            '-' => -1,
+            _ => break,
+        };
+        *pos += 1;
+        let n = match chars.get(*pos) {
+            Some(d) if d.is_ascii_digit() => parse_number(chars, pos),
+            _ => 1,
+        };
+        offset += sign * n as isize;
+    }
+
+    Ok(Some(Address { base, offset }))
+}
+
+fn parse_number(chars: &[char], pos: &mut usize) -> usize {
+    let mut n = 0;
+    while let Some(d) = chars.get(*pos).and_then(|c| c.to_digit(10)) {
+        n = n * 10 + d as usize;
+        *pos += 1;
+    }
+    n
+}
+
+impl Address {
+    // 1-based line number in `buf`, `current` is the cursor line
+    pub fn resolve(&self, buf: &Buffer, current: usize) -> Result<usize, Error> {
+        let base = match self.base {
+            Base::Line(n) => n,
+            Base::Current => current,
+            Base::Last => last_line(buf),
+            Base::Mark(m) => match buf.marks.get(&m) {
+                Some(line) => *line,
+                None => return Err(Error::MarkNotSet),
+            },
+        };
+        let line = base as isize + self.offset;
+        if line < 0 || line as usize > last_line(buf) {
+            return Err(Error::InvalidRange);
+        }
+        Ok(line as usize)
+    }
+}
+
+// The last line with text in it, ropey counts an empty one after a
+// trailing newline
+fn last_line(buf: &Buffer) -> usize {
+    let n = buf.rope.len_lines();
+    if n > 1 && buf.rope.line(n - 1).len_chars() == 0 {
+        n - 1
+    } else {
+        n
+    }
+}
+
+impl Range {
+    // First and last line, both 1-based and inclusive
+    pub fn resolve(&self, buf: &Buffer, current: usize) -> Result<(usize, usize), Error> {
+        let start = self.start.resolve(buf, current)?;
+        let end = self.end.resolve(buf, current)?;
+        if start > end {
+            return Ok((end.max(1), start));
+        }
+        Ok((start.max(1), end.max(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(base: Base, offset: isize) -> Address {
+        Address { base, offset }
+    }
+
+    fn range(input: &str) -> Option<Range> {
+        Command::parse(input).unwrap().range
+    }
+
+    // Lines 1-5, with the cursor on 3 and a selection of 2-4
+    fn resolve(input: &str) -> Result<(usize, usize), Error> {
+        let mut buf = Buffer::new().set_text("a\nb\nc\nd\ne\n");
+        buf.marks.insert('<', 2);
+        buf.marks.insert('>', 4);
+        match range(input) {
+            Some(r) => r.resolve(&buf, 3),
+            None => Err(Error::MissingArgument),
+        }
+    }
+
+    #[test]
+    fn parse_commands() {
+        let cmd = Command::parse(" w  some file ").unwrap();
+        assert_eq!(cmd.range, None);
+        assert_eq!((cmd.name.as_str(), cmd.bang), ("w", false));
+        assert_eq!(cmd.args, "some file");
+
+        let cmd = Command::parse("q!").unwrap();
+        assert_eq!(
+            (cmd.name.as_str(), cmd.bang, cmd.args.as_str()),
+            ("q", true, "")
+        );
+
+        let cmd = Command::parse("%s/a/b/g").unwrap();
+        assert_eq!((cmd.name.as_str(), cmd.args.as_str()), ("s", "/a/b/g"));
+        assert_eq!(Command::parse("&&").unwrap().name, "&");
+        assert_eq!(Command::parse("42").unwrap().name, "");
+    }
+
+    #[test]
+    fn parse_addresses() {
+        let r = range("%d").unwrap();
+        assert_eq!(
+            (r.start, r.end),
+            (addr(Base::Line(1), 0), addr(Base::Last, 0))
+        );
+
+        let r = range(".,$d").unwrap();
+        assert_eq!(
+            (r.start, r.end),
+            (addr(Base::Current, 0), addr(Base::Last, 0))
+        );
+
+        let r = range("'<,'>s/a/b/").unwrap();
+        assert_eq!(r.start, addr(Base::Mark('<'), 0));
+        assert_eq!(r.end, addr(Base::Mark('>'), 0));
+
+        let r = range("10").unwrap();
+        assert_eq!(r.start, r.end);
+        assert_eq!(r.start, addr(Base::Line(10), 0));
+
+        assert_eq!(range(".+2").unwrap().start, addr(Base::Current, 2));
+        assert_eq!(range("$-1").unwrap().start, addr(Base::Last, -1));
+        assert_eq!(range("+").unwrap().start, addr(Base::Current, 1));
+        assert_eq!(range("-3d").unwrap().start, addr(Base::Current, -3));
+        assert_eq!(range("5+2-").unwrap().start, addr(Base::Line(5), 1));
+        assert_eq!(range("'a+1").unwrap().start, addr(Base::Mark('a'), 1));
+    }
+
+    #[test]
+    fn bad_ranges() {
+        assert!(matches!(Command::parse("1,d"), Err(Error::InvalidRange)));
+        assert!(matches!(Command::parse("'"), Err(Error::InvalidRange)));
+        assert!(matches!(resolve("7"), Err(Error::InvalidRange)));
+        assert!(matches!(resolve(".-4"), Err(Error::InvalidRange)));
+        assert!(matches!(resolve("$+1"), Err(Error::InvalidRange)));
+        assert!(matches!(resolve("'x"), Err(Error::MarkNotSet)));
+    }
+
+    #[test]
+    fn resolve_ranges() {
+        assert_eq!(resolve("%").unwrap(), (1, 5));
+        assert_eq!(resolve(".").unwrap(), (3, 3));
+        assert_eq!(resolve("$").unwrap(), (5, 5));
+        assert_eq!(resolve("'<,'>").unwrap(), (2, 4));
+        assert_eq!(resolve(".-1,.+2").unwrap(), (2, 5));
+        assert_eq!(resolve("$-2,$").unwrap(), (3, 5));
+        // Backwards ranges are turned around, 0 is the first line
+        assert_eq!(resolve("4,2").unwrap(), (2, 4));
+        assert_eq!(resolve("0").unwrap(), (1, 1));
+    }
+
+    #[test]
+    fn last_line_without_a_newline() {
+        let buf = Buffer::new().set_text("a\nb");
+        assert_eq!(range("$").unwrap().resolve(&buf, 1).unwrap(), (2, 2));
+        let buf = Buffer::new().set_text("");
+        assert_eq!(range("%").unwrap().resolve(&buf, 1).unwrap(), (1, 1));
+        let buf = Buffer::new().set_text("a\n\n");
+        assert_eq!(range("$").unwrap().resolve(&buf, 1).unwrap(), (2, 2));
+    }
+}
@@ -1,34 +1,195 @@
 use super::buffer::Buffer;
-use std::path::PathBuf;
+use super::history::LineHistory;
+use super::register::Registers;
+use crate::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
 // Overall editor mechanism
 
-pub struct Editor<'a> {
+pub struct Editor {
     pub dir: Option<PathBuf>,
     pub anon: bool,
+    pub backup: bool,
+    pub save_on_exit: bool,
+    // Send yanks to the terminal's clipboard too
+    pub clipboard: bool,
     pub num_buf: usize,
-    pub cur_buf: Option<&'a Buffer<'a>>,
-    pub buffers: Vec<&'a Buffer<'a>>,
+    // Id of the current buffer
+    pub cur_buf: Option<usize>,
+    // Open buffers, in the order they were opened
+    pub buffers: Vec<Buffer>,
+    next_id: usize,
+    // Lines entered after `:`
+    pub cmd_history: LineHistory,
+    // Patterns searched for with `/` and `?`
+    pub search_history: LineHistory,
+    pub registers: Registers,
 }
 
-impl<'a> Editor<'a> {
-    pub fn new() -> Editor<'a> {
+impl Editor {
+    pub fn new() -> Editor {
         Editor {
             dir: None,
             anon: false,
+            backup: false,
+            save_on_exit: false,
+            clipboard: false,
             num_buf: 0,
             cur_buf: None,
             buffers: Vec::new(),
+            next_id: 1,
+            cmd_history: LineHistory::new().set_name("cmd_history"),
+            search_history: LineHistory::new().set_name("search_history"),
+            registers: Registers::new(),
         }
     }
 
-    pub fn set_dir(mut self, dir: PathBuf) -> Editor<'a> {
+    pub fn set_dir(mut self, dir: PathBuf) -> Editor {
         self.dir = Some(dir);
         self
     }
 
-    pub fn set_anon(mut self) -> Editor<'a> {
+    pub fn set_anon(mut self) -> Editor {
         self.anon = true;
         self
     }
+
+    pub fn set_backup(mut self, backup: bool) -> Editor {
+        self.backup = backup;
+        self
+    }
+
+    pub fn set_save_on_exit(mut self, save_on_exit: bool) -> Editor {
+        self.save_on_exit = save_on_exit;
+        self
+    }
+
+    pub fn set_clipboard(mut self, clipboard: bool) -> Editor {
+        self.clipboard = clipboard;
+        self
+    }
+
+    // Take ownership of a buffer and make it current. Returns its id.
+    pub fn add(&mut self, mut buf: Buffer) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        buf.id = id;
+        self.buffers.push(buf);
+        self.num_buf = self.buffers.len();
+        self.cur_buf = Some(id);
+        id
+    }
+
+    // Open a file, or switch to it if it is already open
+    pub fn open(&mut self, p: PathBuf) -> usize {
+        match self.find_path(&p) {
+            Some(id) => {
+                self.cur_buf = Some(id);
+                id
+            }
+            None => self.add(Buffer::new().set_path(p)),
+        }
+    }
+
+    // Id of the buffer editing file `p`
+    pub fn find_path(&self, p: &Path) -> Option<usize> {
+        let full = fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        let existing = self.buffers.iter().find(|b| match &b.p {
+            Some(x) => fs::canonicalize(x).unwrap_or_else(|_| x.clone()) == full,
+            None => false,
+        });
+        existing.map(|b| b.id)
+    }
+
+    // Close a buffer. Fails on unsaved changes unless `force` is set.
+    pub fn close(&mut self, id: usize, force: bool) -> Result<(), Error> {
+        let i = match self.index_of(id) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        if !force && self.buffers[i].is_modified() {
+            return Err(Error::UnsavedChanges);
+        }
+
+        let buf = self.buffers.remove(i);
+        let _ = buf.write_undo_file();
+        self.num_buf = self.buffers.len();
+
+        if self.cur_buf == Some(id) {
+            // Fall back to the buffer before it
+            let prev = if i > 0 { i - 1 } else { 0 };
+            self.cur_buf = self.buffers.get(prev).map(|b| b.id);
+        }
+        Ok(())
+    }
+
+    pub fn switch_to(&mut self, id: usize) -> bool {
+        if self.index_of(id).is_some() {
+            self.cur_buf = Some(id);
+            return true;
+        }
+        false
+    }
+
+    pub fn next_buf(&mut self) {
+        self.cycle(1);
+    }
+
+    pub fn prev_buf(&mut self) {
+        self.cycle(self.buffers.len().saturating_sub(1));
+    }
+
+    fn cycle(&mut self, step: usize) {
+        let n = self.buffers.len();
+        if let Some(i) = self.cur_buf.and_then(|id| self.index_of(id)) {
+            self.cur_buf = Some(self.buffers[(i + step) % n].id);
+        }
+    }
+
+    fn index_of(&self, id: usize) -> Option<usize> {
+        self.buffers.iter().position(|b| b.id == id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Buffer> {
+        self.buffers.iter().find(|b| b.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Buffer> {
+        self.buffers.iter_mut().find(|b| b.id == id)
+    }
+
+    // (id, name, modified) of every open buffer
+    pub fn list(&self) -> Vec<(usize, String, bool)> {
+        self.buffers
+            .iter()
+            .map(|b| (b.id, b.display_name(), b.is_modified()))
+            .collect()
+    }
+
+    // Save every modified buffer
+    pub fn save_all(&mut self) -> io::Result<()> {
+        let backup = self.backup;
+        for buf in self.buffers.iter_mut() {
+            if buf.p.is_some() && buf.is_modified() {
+                buf.save(backup)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn modified_buffers(&self) -> Vec<&Buffer> {
+        self.buffers.iter().filter(|b| b.is_modified()).collect()
+    }
+
+    pub fn cur_buf(&self) -> Option<&Buffer> {
+        self.get(self.cur_buf?)
+    }
+
+    pub fn cur_buf_mut(&mut self) -> Option<&mut Buffer> {
+        let id = self.cur_buf?;
+        self.get_mut(id)
+    }
 }
@@ -0,0 +1,93 @@
+// History of lines typed at a prompt, like `:` commands
+// Kept in a file in the data dir so it survives restarts
+use crate::config;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const MAX_ENTRIES: usize = 200;
+
+pub struct LineHistory {
+    pub entries: Vec<String>,
+    file: Option<PathBuf>,
+    // Entry being looked at while browsing with Up/Down
+    browsing: Option<usize>,
+}
+
+impl LineHistory {
+    pub fn new() -> LineHistory {
+        LineHistory {
+            entries: Vec::new(),
+            file: None,
+            browsing: None,
+        }
+    }
+
+    // Load history stored under `name` in the data dir
+    pub fn set_name(mut self, name: &str) -> LineHistory {
+        let file = config::data_dir().join(name);
+        if let Ok(s) = fs::read_to_string(&file) {
+            self.entries = s.lines().map(|l| l.to_string()).collect();
+        }
+        self.file = Some(file);
+        self
+    }
+
+    // Add an entry, moving it to the end if it was already there
+    pub fn push(&mut self, line: &str) {
+        self.browsing = None;
+        if line.trim().is_empty() {
+            return;
+        }
+        self.entries.retain(|e| e != line);
+        self.entries.push(line.to_string());
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let file = match &self.file {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = self.entries.join("\n");
+        out.push('\n');
+        fs::write(file, out)
+    }
+
+    // Older entry, for Up
+    pub fn prev(&mut self) -> Option<&str> {
+        let i = match self.browsing {
+            Some(0) => 0,
+            Some(i) => i - 1,
+            None => self.entries.len().checked_sub(1)?,
+        };
+        self.browsing = Some(i);
+        self.entries.get(i).map(|e| e.as_str())
+    }
+
+    // Newer entry, for Down. None past the newest one.
+    pub fn next(&mut self) -> Option<&str> {
+        let i = self.browsing? + 1;
+        if i >= self.entries.len() {
+            self.browsing = None;
+            return None;
+        }
+        self.browsing = Some(i);
+        self.entries.get(i).map(|e| e.as_str())
+    }
+
+    pub fn reset(&mut self) {
+        self.browsing = None;
+    }
+}
+
+impl Default for LineHistory {
+    fn default() -> Self {
+        LineHistory::new()
+    }
+}
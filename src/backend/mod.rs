@@ -2,7 +2,23 @@
 // Has:
 //  - Buffer
 //  - Editor
+//  - Undo history
+//  - Ex commands
+//  - Motions and operators
+//  - Registers
+//  - Search
+//  - Visual selections
+//  - Text objects
 //  - And More...!
 
 pub mod buffer;
+pub mod command;
 pub mod editor;
+pub mod history;
+pub mod motion;
+pub mod operator;
+pub mod register;
+pub mod search;
+pub mod selection;
+pub mod textobject;
+pub mod undo;
@@ -0,0 +1,549 @@
+// Cursor motions over a Buffer
+// Positions are char indices into the rope, lines are 0-based
+
+use super::buffer::Buffer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CharClass {
+    Space,
+    Word,
+    Punct,
+}
+
+// With `big`, everything but whitespace is one class (W, B, E)
+pub fn class(c: char, big: bool) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if big || c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punct
+    }
+}
+
+// An empty line starts at `idx`
+fn is_empty_line(buf: &Buffer, idx: usize) -> bool {
+    buf.rope.char(idx) == '\n' && (idx == 0 || buf.rope.char(idx - 1) == '\n')
+}
+
+pub fn line_start(buf: &Buffer, line: usize) -> usize {
+    buf.rope.line_to_char(line)
+}
+
+// Last char of the line, or its start when empty
+pub fn line_end(buf: &Buffer, line: usize) -> usize {
+    line_start(buf, line) + buf.line_len(line).saturating_sub(1)
+}
+
+// ^
+pub fn first_non_blank(buf: &Buffer, line: usize) -> usize {
+    let start = line_start(buf, line);
+    let len = buf.line_len(line);
+    let blanks = buf
+        .rope
+        .line(line)
+        .chars()
+        .take(len)
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .count();
+    start + blanks.min(len.saturating_sub(1))
+}
+
+// h, stays on the line
+pub fn left(buf: &Buffer, idx: usize, count: usize) -> usize {
+    let start = line_start(buf, buf.rope.char_to_line(idx));
+    idx.saturating_sub(count).max(start)
+}
+
+// l, stays on the line
+pub fn right(buf: &Buffer, idx: usize, count: usize) -> usize {
+    let end = line_end(buf, buf.rope.char_to_line(idx));
+    (idx + count).min(end).max(idx.min(end))
+}
+
+// w and W
+pub fn word_forward(buf: &Buffer, idx: usize, big: bool) -> usize {
+    let len = buf.rope.len_chars();
+    if idx >= len {
+        return idx;
+    }
+
+    let mut i = idx;
+    let start = class(buf.rope.char(i), big);
+    if start != CharClass::Space {
+        while i < len && class(buf.rope.char(i), big) == start {
+            i += 1;
+        }
+    }
+    // Skip whitespace, an empty line counts as a word
+    while i < len {
+        let c = buf.rope.char(i);
+        if i > idx && is_empty_line(buf, i) {
+            return i;
+        }
+        if !c.is_whitespace() {
+            break;
+        }
+        i += 1;
+    }
+    i
+}
+
+// b and B
+pub fn word_backward(buf: &Buffer, idx: usize, big: bool) -> usize {
+    if idx == 0 {
+        return 0;
+    }
+
+    let mut i = idx - 1;
+    while i > 0 && buf.rope.char(i).is_whitespace() {
+        if is_empty_line(buf, i) {
+            return i;
+        }
+        i -= 1;
+    }
+    let c = class(buf.rope.char(i), big);
+    while i > 0 && class(buf.rope.char(i - 1), big) == c {
+        i -= 1;
+    }
+    i
+}
+
+// e and E
+pub fn word_end(buf: &Buffer, idx: usize, big: bool) -> usize {
+    let len = buf.rope.len_chars();
+    let mut i = idx + 1;
+    while i < len && buf.rope.char(i).is_whitespace() {
+        i += 1;
+    }
+    if i >= len {
+        return len.saturating_sub(1).max(idx);
+    }
+    let c = class(buf.rope.char(i), big);
+    while i + 1 < len && class(buf.rope.char(i + 1), big) == c {
+        i += 1;
+    }
+    i
+}
+
+// } moves to the next empty line
+pub fn paragraph_forward(buf: &Buffer, line: usize) -> usize {
+    let last = buf.line_count.saturating_sub(1);
+    let mut l = line;
+    // Skip the empty lines we're on
+    while l < last && buf.line_len(l) == 0 {
+        l += 1;
+    }
+    while l < last && buf.line_len(l) != 0 {
+        l += 1;
+    }
+    l
+}
+
+// { moves to the previous empty line
+pub fn paragraph_backward(buf: &Buffer, line: usize) -> usize {
+    let mut l = line;
+    while l > 0 && buf.line_len(l) == 0 {
+        l -= 1;
+    }
+    while l > 0 && buf.line_len(l) != 0 {
+        l -= 1;
+    }
+    l
+}
+
+// f, F, t and T, the char to look for on the line and which way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Find {
+    pub c: char,
+    pub forward: bool,
+    // t and T stop next to the char
+    pub till: bool,
+}
+
+impl Find {
+    // `,` goes the other way than the last find
+    pub fn reversed(self) -> Find {
+        Find {
+            forward: !self.forward,
+            ..self
+        }
+    }
+}
+
+// The `count`th `f.c` after or before `idx` on its line, None when there
+// aren't that many. Repeated with `again` (; and ,), t and T skip the
+// char right next to the cursor instead of staying put.
+pub fn find_char(buf: &Buffer, idx: usize, f: Find, count: usize, again: bool) -> Option<usize> {
+    let line = buf.rope.char_to_line(idx);
+    let start = line_start(buf, line);
+    let end = start + buf.line_len(line);
+    let is_c = |i: &usize| buf.rope.char(*i) == f.c;
+
+    let mut i = idx;
+    for n in 0..count {
+        let skip = if f.till && again && n == 0 { 1 } else { 0 };
+        i = if f.forward {
+            (i + 1 + skip..end).find(is_c)?
+        } else {
+            (start..i.saturating_sub(skip)).rev().find(is_c)?
+        };
+    }
+    Some(match (f.till, f.forward) {
+        (false, _) => i,
+        (true, true) => i - 1,
+        (true, false) => i + 1,
+    })
+}
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+// %, the bracket matching the first one under or after the cursor on its line
+pub fn match_pair(buf: &Buffer, idx: usize) -> Option<usize> {
+    let line = buf.rope.char_to_line(idx);
+    let end = line_start(buf, line) + buf.line_len(line);
+    let (at, open, close) = (idx..end).find_map(|i| {
+        let c = buf.rope.char(i);
+        PAIRS
+            .iter()
+            .find(|(o, cl)| c == *o || c == *cl)
+            .map(|(o, cl)| (i, *o, *cl))
+    })?;
+
+    let mut depth = 0;
+    if buf.rope.char(at) == open {
+        for (i, c) in buf.rope.chars_at(at).enumerate() {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(at + i);
+                }
+            }
+        }
+    } else {
+        for i in (0..=at).rev() {
+            let c = buf.rope.char(i);
+            if c == close {
+                depth += 1;
+            } else if c == open {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+        }
+    }
+    None
+}
+
+// Char index of the screen column `col` on `line`, used to keep
+// the column when moving across short lines
+pub fn char_at_col(buf: &Buffer, line: usize, col: usize, tab_width: usize) -> usize {
+    let start = line_start(buf, line);
+    let mut width = 0;
+    let mut i = 0;
+    for c in buf.rope.line(line).chars().take(buf.line_len(line)) {
+        let w = if c == '\t' {
+            tab_width - width % tab_width
+        } else {
+            1
+        };
+        if width + w > col {
+            break;
+        }
+        width += w;
+        i += 1;
+    }
+    start + i
+}
+
+// Screen column of the char at `idx`, the inverse of char_at_col
+pub fn col_at_char(buf: &Buffer, idx: usize, tab_width: usize) -> usize {
+    let line = buf.rope.char_to_line(idx);
+    let start = line_start(buf, line);
+    buf.rope.slice(start..idx).chars().fold(0, |width, c| {
+        if c == '\t' {
+            width + tab_width - width % tab_width
+        } else {
+            width + 1
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    // `true` for the WORD versions
+    WordForward(bool),
+    WordBackward(bool),
+    WordEnd(bool),
+    ParagraphForward,
+    ParagraphBackward,
+    // gg and G with a count, 0-based
+    GotoLine(usize),
+    LastLine,
+    Find(Find),
+    // ; and , repeating a find
+    FindAgain(Find),
+    MatchPair,
+}
+
+impl Motion {
+    // Motions typed as a single char
+    pub fn from_char(c: char) -> Option<Motion> {
+        let m = match c {
+            'h' => Motion::Left,
+            'l' => Motion::Right,
+            'k' => Motion::Up,
+            'j' => Motion::Down,
+            '0' => Motion::LineStart,
+            '^' => Motion::FirstNonBlank,
+            '$' => Motion::LineEnd,
+            'w' => Motion::WordForward(false),
+            'W' => Motion::WordForward(true),
+            'b' => Motion::WordBackward(false),
+            'B' => Motion::WordBackward(true),
+            'e' => Motion::WordEnd(false),
+            'E' => Motion::WordEnd(true),
+            '}' => Motion::ParagraphForward,
+            '{' => Motion::ParagraphBackward,
+            'G' => Motion::LastLine,
+            '%' => Motion::MatchPair,
+            _ => return None,
+        };
+        Some(m)
+    }
+
+    // Operators act on whole lines
+    pub fn linewise(self) -> bool {
+        matches!(
+            self,
+            Motion::Up | Motion::Down | Motion::GotoLine(_) | Motion::LastLine
+        )
+    }
+
+    // The char under the target is part of the range
+    pub fn inclusive(self) -> bool {
+        match self {
+            Motion::Find(f) | Motion::FindAgain(f) => f.forward,
+            _ => matches!(
+                self,
+                Motion::WordEnd(_) | Motion::LineEnd | Motion::MatchPair
+            ),
+        }
+    }
+
+    // Where the cursor lands, None for a find or % that fails, which
+    // doesn't move it at all
+    pub fn target(self, buf: &Buffer, idx: usize, count: usize) -> Option<usize> {
+        match self {
+            Motion::Find(f) => find_char(buf, idx, f, count, false),
+            Motion::FindAgain(f) => find_char(buf, idx, f, count, true),
+            Motion::MatchPair => match_pair(buf, idx),
+            _ => Some(self.apply(buf, idx, count)),
+        }
+    }
+
+    // Where the cursor lands after moving `count` times from `idx`
+    pub fn apply(self, buf: &Buffer, idx: usize, count: usize) -> usize {
+        let line = buf.rope.char_to_line(idx);
+        let last = buf.line_count.saturating_sub(1);
+        let repeat = |f: &dyn Fn(usize) -> usize, from: usize| (0..count).fold(from, |i, _| f(i));
+
+        match self {
+            Motion::Left => left(buf, idx, count),
+            Motion::Right => right(buf, idx, count),
+            Motion::Up => line_start(buf, line.saturating_sub(count)),
+            Motion::Down => line_start(buf, (line + count).min(last)),
+            Motion::LineStart => line_start(buf, line),
+            Motion::FirstNonBlank => first_non_blank(buf, line),
+            Motion::LineEnd => line_end(buf, (line + count - 1).min(last)),
+            Motion::WordForward(big) => repeat(&|i| word_forward(buf, i, big), idx),
+            Motion::WordBackward(big) => repeat(&|i| word_backward(buf, i, big), idx),
+            Motion::WordEnd(big) => repeat(&|i| word_end(buf, i, big), idx),
+            Motion::ParagraphForward => {
+                line_start(buf, repeat(&|l| paragraph_forward(buf, l), line))
+            }
+            Motion::ParagraphBackward => {
+                line_start(buf, repeat(&|l| paragraph_backward(buf, l), line))
+            }
+            Motion::GotoLine(n) => first_non_blank(buf, n.min(last)),
+            Motion::LastLine => first_non_blank(buf, last),
+            Motion::Find(_) | Motion::FindAgain(_) | Motion::MatchPair => {
+                self.target(buf, idx, count).unwrap_or(idx)
+            }
+        }
+    }
+
+    // Char range an operator covers when moving from `idx`
+    // Returns the range and whether it is made of whole lines
+    pub fn range(self, buf: &Buffer, idx: usize, count: usize) -> (std::ops::Range<usize>, bool) {
+        let mut target = self.apply(buf, idx, count);
+
+        // `dl` on the last char still deletes it
+        if self == Motion::Right {
+            let line = buf.rope.char_to_line(idx);
+            target = (idx + count).min(line_start(buf, line) + buf.line_len(line));
+        }
+
+        // `dw` on the last word of a line doesn't join the next line
+        if let Motion::WordForward(_) = self {
+            let from_line = buf.rope.char_to_line(idx);
+            let to_line = buf.rope.char_to_line(target.min(buf.rope.len_chars()));
+            if to_line > from_line && target <= first_non_blank(buf, to_line) {
+                let prev = to_line - 1;
+                target = line_start(buf, prev) + buf.line_len(prev);
+            }
+        }
+
+        let (start, end) = if target < idx {
+            (target, idx)
+        } else {
+            (idx, target)
+        };
+
+        if self.linewise() {
+            let first = buf.rope.char_to_line(start);
+            let last = buf.rope.char_to_line(end);
+            return (line_range(buf, first, last), true);
+        }
+
+        let len = buf.rope.len_chars();
+        let end = if self.inclusive() { end + 1 } else { end };
+        (start.min(len)..end.min(len), false)
+    }
+}
+
+// Chars of lines `first..=last`, including the last line ending
+pub fn line_range(buf: &Buffer, first: usize, last: usize) -> std::ops::Range<usize> {
+    let start = line_start(buf, first);
+    let end = if last + 1 < buf.line_count {
+        line_start(buf, last + 1)
+    } else {
+        buf.rope.len_chars()
+    };
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Where `m` lands from each index, `count` times
+    fn walk(text: &str, m: Motion, from: usize, count: usize) -> usize {
+        m.apply(&Buffer::new().set_text(text), from, count)
+    }
+
+    fn stops(text: &str, m: Motion, from: usize) -> Vec<usize> {
+        let buf = Buffer::new().set_text(text);
+        let mut out = vec![from];
+        loop {
+            let next = m.apply(&buf, *out.last().unwrap(), 1);
+            if next == *out.last().unwrap() {
+                return out;
+            }
+            out.push(next);
+        }
+    }
+
+    #[test]
+    fn words() {
+        let text = "foo.bar  baz\n\n  x-y";
+        assert_eq!(
+            stops(text, Motion::WordForward(false), 0),
+            vec![0, 3, 4, 9, 13, 16, 17, 18, 19]
+        );
+        assert_eq!(
+            stops(text, Motion::WordForward(true), 0),
+            vec![0, 9, 13, 16, 19]
+        );
+        assert_eq!(
+            stops(text, Motion::WordBackward(false), 18),
+            vec![18, 17, 16, 13, 9, 4, 3, 0]
+        );
+        assert_eq!(
+            stops(text, Motion::WordBackward(true), 18),
+            vec![18, 16, 13, 9, 0]
+        );
+        assert_eq!(
+            stops(text, Motion::WordEnd(false), 0),
+            vec![0, 2, 3, 6, 11, 16, 17, 18]
+        );
+        assert_eq!(stops(text, Motion::WordEnd(true), 0), vec![0, 6, 11, 18]);
+    }
+
+    #[test]
+    fn counts_stop_at_the_ends() {
+        let text = "one two\nthree";
+        assert_eq!(walk(text, Motion::WordForward(false), 0, 2), 8);
+        assert_eq!(walk(text, Motion::WordForward(false), 0, 99), 13);
+        assert_eq!(walk(text, Motion::WordBackward(false), 10, 99), 0);
+        assert_eq!(walk(text, Motion::WordEnd(false), 0, 99), 12);
+        assert_eq!(walk(text, Motion::Right, 1, 99), 6);
+        assert_eq!(walk(text, Motion::Left, 9, 99), 8);
+        assert_eq!(walk(text, Motion::Down, 2, 99), 8);
+        assert_eq!(walk(text, Motion::Up, 9, 99), 0);
+        assert_eq!(walk(text, Motion::LineEnd, 0, 99), 12);
+        assert_eq!(walk(text, Motion::GotoLine(99), 0, 1), 8);
+        assert_eq!(walk(text, Motion::ParagraphForward, 0, 99), 8);
+        assert_eq!(walk("", Motion::WordForward(false), 0, 3), 0);
+        assert_eq!(walk("", Motion::WordEnd(false), 0, 3), 0);
+    }
+
+    #[test]
+    fn find_on_the_line() {
+        let buf = Buffer::new().set_text("a,b,c,d\n,");
+        let f = |c, forward, till| Find { c, forward, till };
+        let at = |m: Motion, idx, count| m.target(&buf, idx, count);
+
+        assert_eq!(at(Motion::Find(f(',', true, false)), 0, 1), Some(1));
+        assert_eq!(at(Motion::Find(f(',', true, false)), 0, 3), Some(5));
+        // Not past the end of the line
+        assert_eq!(at(Motion::Find(f(',', true, false)), 0, 4), None);
+        assert_eq!(at(Motion::Find(f('a', true, false)), 0, 1), None);
+        assert_eq!(at(Motion::Find(f(',', false, false)), 6, 2), Some(3));
+        assert_eq!(at(Motion::Find(f(',', false, false)), 0, 1), None);
+
+        assert_eq!(at(Motion::Find(f(',', true, true)), 0, 1), Some(0));
+        assert_eq!(at(Motion::Find(f(',', true, true)), 0, 2), Some(2));
+        assert_eq!(at(Motion::Find(f(',', false, true)), 6, 1), Some(6));
+        // ; after t moves on to the next one instead of staying put
+        assert_eq!(at(Motion::FindAgain(f(',', true, true)), 0, 1), Some(2));
+        assert_eq!(at(Motion::FindAgain(f(',', true, false)), 1, 1), Some(3));
+        assert_eq!(
+            at(Motion::FindAgain(f(',', true, true).reversed()), 6, 1),
+            Some(4)
+        );
+
+        // A failed find doesn't move
+        assert_eq!(Motion::Find(f('x', true, false)).apply(&buf, 2, 1), 2);
+        assert!(Motion::Find(f(',', true, false)).inclusive());
+        assert!(!Motion::Find(f(',', false, false)).inclusive());
+    }
+
+    #[test]
+    fn matching_brackets() {
+        let buf = Buffer::new().set_text("f(a[1], {b}) (\n)\nx");
+        let at = |idx| match_pair(&buf, idx);
+        assert_eq!(at(1), Some(11));
+        assert_eq!(at(11), Some(1));
+        assert_eq!(at(3), Some(5));
+        assert_eq!(at(5), Some(3));
+        // The first bracket after the cursor on the line
+        assert_eq!(at(0), Some(11));
+        assert_eq!(at(6), Some(10));
+        // Across lines
+        assert_eq!(at(12), Some(15));
+        assert_eq!(at(15), Some(13));
+        assert_eq!(at(17), None);
+        assert_eq!(match_pair(&Buffer::new().set_text("(("), 0), None);
+    }
+}
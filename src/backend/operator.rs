@@ -0,0 +1,230 @@
+// Vi operators, applied to a char range of a Buffer
+use super::buffer::Buffer;
+use super::motion;
+use std::ops::Range;
+
+// Spaces added by `>`
+pub const SHIFT_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Dedent,
+    Upper,
+    Lower,
+    ToggleCase,
+}
+
+impl Operator {
+    // Operators typed as a single char, gU, gu and g~ come after `g`
+    pub fn from_char(c: char) -> Option<Operator> {
+        let op = match c {
+            'd' => Operator::Delete,
+            'c' => Operator::Change,
+            'y' => Operator::Yank,
+            '>' => Operator::Indent,
+            '<' => Operator::Dedent,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // Run the operator over `range`
+    // Returns the text it covered and where the cursor goes
+    pub fn apply(self, buf: &mut Buffer, range: Range<usize>, linewise: bool) -> (String, usize) {
+        let mut range = range;
+        let text = buf.rope.slice(range.clone()).to_string();
+
+        match self {
+            Operator::Yank => (text, range.start),
+            Operator::Delete => {
+                buf.delete(range.clone());
+                let mut cursor = range.start.min(buf.rope.len_chars());
+                if linewise {
+                    // Deleting the last lines leaves the cursor on the line above
+                    let mut line = buf.rope.char_to_line(cursor);
+                    if cursor == buf.rope.len_chars() && line > 0 && range.start > 0 {
+                        line -= 1;
+                        // The last line had no line ending, so the one above
+                        // loses its own
+                        if !text.ends_with('\n') {
+                            buf.delete(cursor - 1..cursor);
+                        }
+                    }
+                    cursor = motion::first_non_blank(buf, line);
+                }
+                (text, cursor)
+            }
+            Operator::Change => {
+                // `cc` keeps the line itself
+                if linewise && text.ends_with('\n') {
+                    range.end -= 1;
+                }
+                let text = buf.rope.slice(range.clone()).to_string();
+                buf.delete(range.clone());
+                (text, range.start)
+            }
+            Operator::Indent | Operator::Dedent => {
+                let first = buf.rope.char_to_line(range.start);
+                let last = buf
+                    .rope
+                    .char_to_line(range.end.saturating_sub(1).max(range.start));
+                // Bottom up, so earlier line starts stay valid
+                for line in (first..=last).rev() {
+                    let start = motion::line_start(buf, line);
+                    if self == Operator::Indent {
+                        if buf.line_len(line) > 0 {
+                            buf.insert_str(start, &" ".repeat(SHIFT_WIDTH));
+                        }
+                    } else {
+                        let width = match buf.rope.line(line).chars().next() {
+                            Some('\t') => 1,
+                            _ => buf
+                                .rope
+                                .line(line)
+                                .chars()
+                                .take(SHIFT_WIDTH)
+                                .take_while(|c| *c == ' ')
+                                .count(),
+                        };
+                        buf.delete(start..start + width);
+                    }
+                }
+                (text, motion::first_non_blank(buf, first))
+            }
+            Operator::Upper | Operator::Lower | Operator::ToggleCase => {
+                let changed = match self {
+                    Operator::Upper => text.to_uppercase(),
+                    Operator::Lower => text.to_lowercase(),
+                    _ => text
+                        .chars()
+                        .flat_map(|c| {
+                            if c.is_uppercase() {
+                                c.to_lowercase().collect::<Vec<_>>()
+                            } else {
+                                c.to_uppercase().collect()
+                            }
+                        })
+                        .collect(),
+                };
+                if changed != text {
+                    buf.delete(range.clone());
+                    buf.insert_str(range.start, &changed);
+                }
+                (text, range.start)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The buffer text after `op`, with what it covered and the cursor
+    fn run(
+        op: Operator,
+        text: &str,
+        range: Range<usize>,
+        linewise: bool,
+    ) -> (String, String, usize) {
+        let mut buf = Buffer::new().set_text(text);
+        let (covered, cursor) = op.apply(&mut buf, range, linewise);
+        (buf.rope.to_string(), covered, cursor)
+    }
+
+    #[test]
+    fn delete_chars() {
+        let (text, covered, cursor) = run(Operator::Delete, "hello world\n", 0..6, false);
+        assert_eq!(
+            (text.as_str(), covered.as_str(), cursor),
+            ("world\n", "hello ", 0)
+        );
+        // Up to the end of the buffer
+        let (text, _, cursor) = run(Operator::Delete, "ab\ncd", 3..5, false);
+        assert_eq!((text.as_str(), cursor), ("ab\n", 3));
+    }
+
+    #[test]
+    fn delete_lines() {
+        // The cursor goes to the first non blank of the line below
+        let (text, covered, cursor) = run(Operator::Delete, "a\nb\n  c\n", 2..4, true);
+        assert_eq!(
+            (text.as_str(), covered.as_str(), cursor),
+            ("a\n  c\n", "b\n", 4)
+        );
+        // The last line, the cursor moves up
+        let (text, _, cursor) = run(Operator::Delete, "a\n  b\nc\n", 6..8, true);
+        assert_eq!((text.as_str(), cursor), ("a\n  b\n", 4));
+        // The last line has no line ending, the one above loses its own
+        let (text, covered, cursor) = run(Operator::Delete, "a\nb", 2..3, true);
+        assert_eq!((text.as_str(), covered.as_str(), cursor), ("a", "b", 0));
+        // Every line
+        let (text, _, cursor) = run(Operator::Delete, "a\nb\n", 0..4, true);
+        assert_eq!((text.as_str(), cursor), ("", 0));
+        let (text, _, cursor) = run(Operator::Delete, "a\nb", 0..3, true);
+        assert_eq!((text.as_str(), cursor), ("", 0));
+    }
+
+    #[test]
+    fn change() {
+        let (text, covered, cursor) = run(Operator::Change, "foo bar\n", 4..7, false);
+        assert_eq!(
+            (text.as_str(), covered.as_str(), cursor),
+            ("foo \n", "bar", 4)
+        );
+        // `cc` keeps the line ending
+        let (text, covered, cursor) = run(Operator::Change, "a\n  b\nc\n", 2..6, true);
+        assert_eq!(
+            (text.as_str(), covered.as_str(), cursor),
+            ("a\n\nc\n", "  b", 2)
+        );
+        let (text, covered, _) = run(Operator::Change, "a\nb", 2..3, true);
+        assert_eq!((text.as_str(), covered.as_str()), ("a\n", "b"));
+    }
+
+    #[test]
+    fn yank() {
+        let mut buf = Buffer::new().set_text("a\nb\n");
+        let (covered, cursor) = Operator::Yank.apply(&mut buf, 2..4, true);
+        assert_eq!((covered.as_str(), cursor), ("b\n", 2));
+        assert_eq!(buf.rope.to_string(), "a\nb\n");
+        assert!(!buf.is_modified());
+    }
+
+    #[test]
+    fn shift() {
+        // Empty lines stay empty
+        let (text, _, cursor) = run(Operator::Indent, "a\n\n b\n", 0..6, true);
+        assert_eq!((text.as_str(), cursor), ("    a\n\n     b\n", 4));
+        // A range ending on a line ending doesn't take the next line
+        let (text, _, _) = run(Operator::Indent, "a\nb\n", 0..2, false);
+        assert_eq!(text, "    a\nb\n");
+        // At most one shift width, or one tab
+        let (text, _, cursor) = run(Operator::Dedent, "      a\n\t\tb\n  c\nd\n", 0..18, true);
+        assert_eq!((text.as_str(), cursor), ("  a\n\tb\nc\nd\n", 2));
+        // An empty range still shifts its line
+        let (text, _, _) = run(Operator::Indent, "a\nb\n", 2..2, false);
+        assert_eq!(text, "a\n    b\n");
+    }
+
+    #[test]
+    fn case() {
+        let (text, covered, cursor) = run(Operator::Upper, "Hello World\n", 0..5, false);
+        assert_eq!(
+            (text.as_str(), covered.as_str(), cursor),
+            ("HELLO World\n", "Hello", 0)
+        );
+        let (text, _, _) = run(Operator::Lower, "Hello World\n", 0..12, true);
+        assert_eq!(text, "hello world\n");
+        let (text, _, _) = run(Operator::ToggleCase, "Hello World\n", 3..8, false);
+        assert_eq!(text, "HelLO wOrld\n");
+        // Nothing to change, nothing recorded
+        let mut buf = Buffer::new().set_text("abc\n");
+        Operator::Lower.apply(&mut buf, 0..3, false);
+        assert!(!buf.is_modified());
+    }
+}
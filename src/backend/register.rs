@@ -0,0 +1,264 @@
+// Text stored by yanks and deletes
+// Registers are picked with `"x`:
+//  - `"` the unnamed one, written by every yank and delete
+//  - `0` the last yank, `1`-`9` the last deletes of whole lines
+//  - `-` the last delete within a line
+//  - `a`-`z` named registers, `A`-`Z` append to them
+//  - `+` and `*` also go to the system clipboard
+//  - `_` the black hole, drops whatever is written to it
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct Register {
+    pub text: String,
+    // Yanked whole lines
+    pub linewise: bool,
+    // Yanked with Ctrl-v, one line of `text` per row of the block
+    pub blockwise: bool,
+}
+
+impl Register {
+    pub fn new(text: String, linewise: bool) -> Register {
+        Register {
+            text,
+            linewise,
+            blockwise: false,
+        }
+    }
+
+    // The rows of a block, pasted one below the other
+    pub fn block(rows: &[String]) -> Register {
+        Register {
+            text: rows.join("\n"),
+            linewise: false,
+            blockwise: true,
+        }
+    }
+}
+
+pub struct Registers {
+    unnamed: Register,
+    numbered: Vec<Register>,
+    small_delete: Register,
+    named: HashMap<char, Register>,
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            unnamed: Register::default(),
+            numbered: vec![Register::default(); 10],
+            small_delete: Register::default(),
+            named: HashMap::new(),
+        }
+    }
+
+    // Can be typed after `"`
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '+' | '*' | '_')
+    }
+
+    pub fn get(&self, name: char) -> Option<&Register> {
+        let reg = match name {
+            '"' => &self.unnamed,
+            '-' => &self.small_delete,
+            '0'..='9' => &self.numbered[name as usize - '0' as usize],
+            'a'..='z' | '+' | '*' => self.named.get(&name)?,
+            'A'..='Z' => self.named.get(&name.to_ascii_lowercase())?,
+            _ => return None,
+        };
+        Some(reg)
+    }
+
+    // Put `reg` in register `name`, leaving the others alone
+    pub fn set(&mut self, name: char, reg: Register) {
+        match name {
+            '"' => self.unnamed = reg,
+            '-' => self.small_delete = reg,
+            '0'..='9' => self.numbered[name as usize - '0' as usize] = reg,
+            'a'..='z' | '+' | '*' => {
+                self.named.insert(name, reg);
+            }
+            'A'..='Z' => {
+                let old = self.named.entry(name.to_ascii_lowercase()).or_default();
+                if old.text.is_empty() {
+                    *old = reg;
+                    return;
+                }
+                // Appending lines to text makes it lines too, a block
+                // to a block adds rows
+                let rows = reg.blockwise && old.blockwise;
+                if rows || reg.linewise && !old.linewise {
+                    old.text.push('\n');
+                }
+                old.text.push_str(&reg.text);
+                old.linewise |= reg.linewise;
+                old.blockwise = rows;
+            }
+            _ => (),
+        }
+    }
+
+    // Record a yank or a delete, `name` is the register picked with `"x`
+    pub fn store(&mut self, name: Option<char>, reg: Register, yank: bool) {
+        match name {
+            Some('_') => return,
+            Some(n) if n != '"' => {
+                self.set(n, reg);
+                if let Some(r) = self.get(n) {
+                    self.unnamed = r.clone();
+                }
+                return;
+            }
+            _ => (),
+        }
+
+        if yank {
+            self.numbered[0] = reg.clone();
+        } else if reg.linewise || reg.text.contains('\n') {
+            // Shift the delete history down
+            self.numbered.pop();
+            self.numbered.insert(1, reg.clone());
+        } else {
+            self.small_delete = reg.clone();
+        }
+        self.unnamed = reg;
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers::new()
+    }
+}
+
+// Escape sequence asking the terminal to put `text` on the clipboard.
+// Works over ssh and without any clipboard tool installed.
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(regs: &Registers, name: char) -> Option<(&str, bool)> {
+        regs.get(name).map(|r| (r.text.as_str(), r.linewise))
+    }
+
+    #[test]
+    fn yanks_and_deletes() {
+        let mut regs = Registers::new();
+        regs.store(None, Register::new("word".to_string(), false), true);
+        assert_eq!(text(&regs, '"'), Some(("word", false)));
+        assert_eq!(text(&regs, '0'), Some(("word", false)));
+
+        // Deletes within a line go to `-` and leave the yank alone
+        regs.store(None, Register::new("x".to_string(), false), false);
+        assert_eq!(text(&regs, '-'), Some(("x", false)));
+        assert_eq!(text(&regs, '"'), Some(("x", false)));
+        assert_eq!(text(&regs, '0'), Some(("word", false)));
+        assert_eq!(text(&regs, '1'), Some(("", false)));
+
+        // Line deletes shift through 1-9
+        for i in 1..=10 {
+            regs.store(None, Register::new(format!("line {}\n", i), true), false);
+        }
+        assert_eq!(text(&regs, '1'), Some(("line 10\n", true)));
+        assert_eq!(text(&regs, '9'), Some(("line 2\n", true)));
+        assert_eq!(text(&regs, '-'), Some(("x", false)));
+        // A charwise delete across lines counts too
+        regs.store(None, Register::new("a\nb".to_string(), false), false);
+        assert_eq!(text(&regs, '1'), Some(("a\nb", false)));
+        assert_eq!(text(&regs, '2'), Some(("line 10\n", true)));
+    }
+
+    #[test]
+    fn named_registers() {
+        let mut regs = Registers::new();
+        assert!(regs.get('a').is_none());
+        regs.store(Some('a'), Register::new("one".to_string(), false), true);
+        assert_eq!(text(&regs, 'a'), Some(("one", false)));
+        assert_eq!(text(&regs, 'A'), Some(("one", false)));
+        assert_eq!(text(&regs, '"'), Some(("one", false)));
+        // Picking a register keeps 0 and the delete history as they were
+        assert_eq!(text(&regs, '0'), Some(("", false)));
+
+        regs.store(Some('A'), Register::new("two".to_string(), false), true);
+        assert_eq!(text(&regs, 'a'), Some(("onetwo", false)));
+        assert_eq!(text(&regs, '"'), Some(("onetwo", false)));
+        regs.store(Some('A'), Register::new("three\n".to_string(), true), false);
+        assert_eq!(text(&regs, 'a'), Some(("onetwo\nthree\n", true)));
+
+        // Appending to an empty register is just setting it
+        regs.store(Some('B'), Register::new("b\n".to_string(), true), true);
+        assert_eq!(text(&regs, 'b'), Some(("b\n", true)));
+
+        regs.store(Some('+'), Register::new("clip".to_string(), false), true);
+        assert_eq!(text(&regs, '+'), Some(("clip", false)));
+        assert!(regs.get('*').is_none());
+    }
+
+    #[test]
+    fn blocks() {
+        let mut regs = Registers::new();
+        let rows = ["ab".to_string(), String::new()];
+        regs.store(Some('c'), Register::block(&rows), true);
+        assert_eq!(text(&regs, 'c'), Some(("ab\n", false)));
+        assert!(regs.get('"').unwrap().blockwise);
+        // A block appended to a block adds rows
+        regs.store(Some('C'), Register::block(&["cd".to_string()]), true);
+        assert_eq!(text(&regs, 'c'), Some(("ab\n\ncd", false)));
+        assert!(regs.get('c').unwrap().blockwise);
+        // Anything else makes it plain text
+        regs.store(Some('C'), Register::new("e".to_string(), false), true);
+        assert_eq!(text(&regs, 'c'), Some(("ab\n\ncde", false)));
+        assert!(!regs.get('c').unwrap().blockwise);
+    }
+
+    #[test]
+    fn black_hole_and_invalid() {
+        let mut regs = Registers::new();
+        regs.store(None, Register::new("kept".to_string(), false), true);
+        regs.store(Some('_'), Register::new("gone".to_string(), false), false);
+        assert_eq!(text(&regs, '"'), Some(("kept", false)));
+        assert!(regs.get('_').is_none());
+
+        assert!(Registers::is_valid('a') && Registers::is_valid('Z') && Registers::is_valid('7'));
+        assert!(!Registers::is_valid('!') && !Registers::is_valid(' '));
+        regs.set('!', Register::new("x".to_string(), false));
+        assert!(regs.get('!').is_none());
+    }
+
+    #[test]
+    fn clipboard_escape() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe, 0x00]), "//4A");
+        assert_eq!(osc52("hé"), "\x1b]52;c;aMOp\x07");
+    }
+}
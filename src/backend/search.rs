@@ -0,0 +1,353 @@
+// Regex search over a Buffer
+// Patterns are smart-case: without uppercase letters they match either case
+use super::buffer::Buffer;
+use super::motion::{self, CharClass};
+use crate::error::Error;
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::ops::Range;
+
+pub fn compile(pattern: &str) -> Result<Regex, Error> {
+    // Escapes like `\W` don't count as uppercase
+    let mut ignore_case = true;
+    let mut escaped = false;
+    for c in pattern.chars() {
+        if c.is_uppercase() && !escaped {
+            ignore_case = false;
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .build()
+        .map_err(|_| Error::InvalidPattern {
+            pattern: pattern.to_string(),
+        })
+}
+
+// Char range of the next match after `idx`, or the one before it when
+// going backward. Wraps around the ends of the buffer, the bool tells
+// when it did. Goes line by line out from the cursor, so it only looks
+// as far as the match, and like `:s` matches don't span lines.
+pub fn find(buf: &Buffer, re: &Regex, idx: usize, forward: bool) -> Option<(Range<usize>, bool)> {
+    let lines = buf.rope.len_lines();
+    let cur = buf.rope.char_to_line(idx.min(buf.rope.len_chars()));
+
+    // Every line once, then the cursor's line again for what's on the other side
+    for k in 0..=lines {
+        let (line, wrapped) = if forward {
+            ((cur + k) % lines, cur + k >= lines)
+        } else {
+            ((cur + lines - k) % lines, k > cur)
+        };
+        let found = on_line(buf, re, line);
+        let m = match (k, forward) {
+            (0, true) => found.into_iter().find(|m| m.start > idx),
+            (0, false) => found.into_iter().rev().find(|m| m.start < idx),
+            (_, true) => found.into_iter().next(),
+            (_, false) => found.into_iter().next_back(),
+        };
+        if let Some(m) = m {
+            return Some((m, wrapped));
+        }
+    }
+    None
+}
+
+// Matches on `line`, relative to its start, for highlighting
+pub fn line_matches(buf: &Buffer, re: &Regex, line: usize) -> Vec<Range<usize>> {
+    let start = buf.rope.line_to_char(line);
+    on_line(buf, re, line)
+        .into_iter()
+        .filter(|m| m.start < m.end)
+        .map(|m| m.start - start..m.end - start)
+        .collect()
+}
+
+// Char ranges of the matches on `line`, borrowing the text from the
+// rope unless it's split across chunks
+fn on_line(buf: &Buffer, re: &Regex, line: usize) -> Vec<Range<usize>> {
+    let start = buf.rope.line_to_char(line);
+    let slice = buf.rope.line(line);
+    let text: Cow<str> = slice.into();
+    re.find_iter(&text)
+        .map(|m| start + slice.byte_to_char(m.start())..start + slice.byte_to_char(m.end()))
+        .collect()
+}
+
+// Pattern for * and #, the word under or after the cursor on its line
+// Also returns where the word starts
+pub fn word_pattern(buf: &Buffer, idx: usize) -> Option<(String, usize)> {
+    let line = buf.rope.char_to_line(idx);
+    let end = motion::line_start(buf, line) + buf.line_len(line);
+    let is_word = |i: usize| motion::class(buf.rope.char(i), false) == CharClass::Word;
+
+    let mut start = idx;
+    while start < end && !is_word(start) {
+        start += 1;
+    }
+    if start >= end {
+        return None;
+    }
+    // Back up to the start of the word the cursor is in
+    while start > 0 && is_word(start - 1) {
+        start -= 1;
+    }
+    let mut stop = start;
+    while stop < end && is_word(stop) {
+        stop += 1;
+    }
+
+    let word = buf.rope.slice(start..stop).to_string();
+    Some((format!(r"\b{}\b", regex::escape(&word)), start))
+}
+
+// `:s/pattern/replacement/flags`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Substitute {
+    pub pattern: String,
+    // In the syntax of the regex crate, `\1` and `&` are `${1}` and `${0}`
+    pub replacement: String,
+    // g, every match on a line instead of the first
+    pub global: bool,
+    // c, ask before each one
+    pub confirm: bool,
+}
+
+impl Substitute {
+    // Replace every match of `pattern` with `with`, written like in `:s`
+    pub fn new(pattern: &str, with: &str) -> Substitute {
+        Substitute {
+            pattern: pattern.to_string(),
+            replacement: replacement(with),
+            global: true,
+            confirm: false,
+        }
+    }
+
+    // Parse what follows `:s`, the first char is the delimiter
+    pub fn parse(args: &str) -> Result<Substitute, Error> {
+        let mut chars = args.chars();
+        let delim = match chars.next() {
+            Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+            _ => return Err(Error::MissingArgument),
+        };
+
+        // `\/` is a literal delimiter
+        let mut parts = Vec::new();
+        let mut part = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(n) if n == delim => part.push(n),
+                    Some(n) => {
+                        part.push('\\');
+                        part.push(n);
+                    }
+                    None => part.push('\\'),
+                },
+                c if c == delim && parts.len() < 2 => parts.push(std::mem::take(&mut part)),
+                c => part.push(c),
+            }
+        }
+        parts.push(part);
+
+        let mut pattern = parts[0].clone();
+        let replacement = parts.get(1).map_or(String::new(), |r| replacement(r));
+        let mut global = false;
+        let mut confirm = false;
+        for f in parts.get(2).map_or("", |f| f.trim()).chars() {
+            match f {
+                'g' => global = true,
+                'c' => confirm = true,
+                'i' => pattern.insert_str(0, "(?i)"),
+                'I' => pattern.insert_str(0, "(?-i)"),
+                _ => {
+                    return Err(Error::InvalidFlags {
+                        flags: parts[2].clone(),
+                    })
+                }
+            }
+        }
+
+        Ok(Substitute {
+            pattern,
+            replacement,
+            global,
+            confirm,
+        })
+    }
+
+    pub fn regex(&self) -> Result<Regex, Error> {
+        compile(&self.pattern)
+    }
+
+    // Matches of `re` on lines `first..=last` (0-based) and what replaces
+    // them, in buffer order. Matches don't span lines.
+    pub fn matches(
+        &self,
+        re: &Regex,
+        buf: &Buffer,
+        first: usize,
+        last: usize,
+    ) -> Vec<(Range<usize>, String)> {
+        let mut found = Vec::new();
+        for line in first..=last.min(buf.line_count.saturating_sub(1)) {
+            let start = motion::line_start(buf, line);
+            let slice = buf.rope.slice(start..start + buf.line_len(line));
+            let text = slice.to_string();
+            for caps in re.captures_iter(&text) {
+                let m = caps.get(0).unwrap();
+                let mut with = String::new();
+                caps.expand(&self.replacement, &mut with);
+                let range =
+                    start + slice.byte_to_char(m.start())..start + slice.byte_to_char(m.end());
+                found.push((range, with));
+                if !self.global {
+                    break;
+                }
+            }
+        }
+        found
+    }
+}
+
+// Turn a vim style replacement into the regex crate's syntax
+// `&` and `\0`-`\9` are the match and its groups, `\r`, `\n` and `\t`
+// are a line break and a tab, `\` escapes anything else
+fn replacement(rep: &str) -> String {
+    let mut out = String::new();
+    let mut chars = rep.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => out.push_str(&format!("${{{}}}", d)),
+                Some('r') | Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('$') => out.push_str("$$"),
+                Some(n) => out.push(n),
+                None => out.push('\\'),
+            },
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(s: &Substitute, text: &str) -> String {
+        let buf = Buffer::new().set_text(text);
+        let re = s.regex().unwrap();
+        let mut out = buf.rope.to_string();
+        // Back to front so the earlier ranges stay put
+        for (range, with) in s.matches(&re, &buf, 0, buf.line_count).into_iter().rev() {
+            let start = buf.rope.char_to_byte(range.start);
+            let end = buf.rope.char_to_byte(range.end);
+            out.replace_range(start..end, &with);
+        }
+        out
+    }
+
+    #[test]
+    fn parse_parts_and_flags() {
+        let s = Substitute::parse("/foo/bar/gc").unwrap();
+        assert_eq!(s.pattern, "foo");
+        assert_eq!(s.replacement, "bar");
+        assert!(s.global && s.confirm);
+
+        let s = Substitute::parse("/foo").unwrap();
+        assert_eq!((s.pattern.as_str(), s.replacement.as_str()), ("foo", ""));
+        assert!(!s.global && !s.confirm);
+
+        assert_eq!(Substitute::parse("/a/b/i").unwrap().pattern, "(?i)a");
+        assert_eq!(Substitute::parse("/A/b/I").unwrap().pattern, "(?-i)A");
+    }
+
+    #[test]
+    fn parse_escaped_delimiter() {
+        let s = Substitute::parse(r"/a\/b/c\/d/").unwrap();
+        assert_eq!(s.pattern, "a/b");
+        assert_eq!(s.replacement, "c/d");
+
+        // Other delimiters, and escapes that aren't the delimiter are kept
+        let s = Substitute::parse(r"#a/b\#c#\d\#e#g").unwrap();
+        assert_eq!(s.pattern, "a/b#c");
+        assert_eq!(s.replacement, "d#e");
+        assert!(s.global);
+        assert_eq!(Substitute::parse(r"/\d+\./x/").unwrap().pattern, r"\d+\.");
+
+        // Past the flags the delimiter is just text, and bad flags say so
+        assert!(matches!(
+            Substitute::parse("/a/b/g/x"),
+            Err(Error::InvalidFlags { .. })
+        ));
+        assert!(matches!(
+            Substitute::parse("a/b/"),
+            Err(Error::MissingArgument)
+        ));
+        assert!(matches!(Substitute::parse(""), Err(Error::MissingArgument)));
+    }
+
+    #[test]
+    fn replacement_syntax() {
+        assert_eq!(replacement("<&>"), "<${0}>");
+        assert_eq!(replacement(r"\2-\1"), "${2}-${1}");
+        assert_eq!(replacement(r"\&"), "&");
+        assert_eq!(replacement("$1"), "$$1");
+        assert_eq!(replacement(r"\$"), "$$");
+        assert_eq!(replacement(r"a\nb\tc\\"), "a\nb\tc\\");
+        assert_eq!(replacement("\\"), "\\");
+    }
+
+    #[test]
+    fn substitutes() {
+        let s = Substitute::parse(r"/(\w+)=(\w+)/\2=\1 [&] $1/").unwrap();
+        assert_eq!(apply(&s, "a=b c=d\n"), "b=a [a=b] $1 c=d\n");
+
+        let s = Substitute::parse(r"/(\w+)=(\w+)/\2=\1 [&] $1/g").unwrap();
+        assert_eq!(apply(&s, "a=b c=d\n"), "b=a [a=b] $1 d=c [c=d] $1\n");
+
+        let s = Substitute::new("o", r"0\n");
+        assert_eq!(apply(&s, "foo\nbo"), "f0\n0\n\nb0\n");
+    }
+
+    #[test]
+    fn finds_and_wraps() {
+        let buf = Buffer::new().set_text("foo bar\nbaz foo\n\nfoo");
+        let re = compile("foo").unwrap();
+        let at = |idx, forward| find(&buf, &re, idx, forward);
+
+        assert_eq!(at(0, true), Some((12..15, false)));
+        assert_eq!(at(12, true), Some((17..20, false)));
+        assert_eq!(at(17, true), Some((0..3, true)));
+        assert_eq!(at(13, false), Some((12..15, false)));
+        assert_eq!(at(12, false), Some((0..3, false)));
+        assert_eq!(at(0, false), Some((17..20, true)));
+
+        // Only the cursor's own match, found again after wrapping
+        let buf = Buffer::new().set_text("a\nfoo\nb");
+        assert_eq!(find(&buf, &re, 2, true), Some((2..5, true)));
+        assert_eq!(find(&buf, &re, 2, false), Some((2..5, true)));
+        assert_eq!(find(&buf, &compile("x").unwrap(), 2, true), None);
+    }
+
+    #[test]
+    fn matches_by_line() {
+        let buf = Buffer::new().set_text("foo\nxfoo foo\n");
+        let re = compile("^foo|foo$").unwrap();
+        assert_eq!(find(&buf, &re, 0, true), Some((9..12, false)));
+        assert_eq!(line_matches(&buf, &re, 1), vec![5..8]);
+        // Empty matches aren't highlighted
+        assert!(line_matches(&buf, &compile("x*").unwrap(), 0).is_empty());
+        assert_eq!(
+            line_matches(&buf, &compile("(?i)FOO").unwrap(), 1),
+            vec![1..4, 5..8]
+        );
+    }
+}
@@ -0,0 +1,179 @@
+// Visual mode selections
+use super::buffer::Buffer;
+use super::motion;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    // v
+    Char,
+    // V
+    Line,
+    // Ctrl-v
+    Block,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Selection {
+    pub kind: Kind,
+    // Where the selection started, it can be on either side of the head
+    pub anchor: usize,
+    // The cursor
+    pub head: usize,
+    // A block after `$` reaches the end of every line
+    pub to_end: bool,
+}
+
+impl Selection {
+    pub fn new(kind: Kind, anchor: usize) -> Selection {
+        Selection {
+            kind,
+            anchor,
+            head: anchor,
+            to_end: false,
+        }
+    }
+
+    fn ordered(&self) -> (usize, usize) {
+        if self.anchor <= self.head {
+            (self.anchor, self.head)
+        } else {
+            (self.head, self.anchor)
+        }
+    }
+
+    // First and last selected line, 0-based
+    pub fn lines(&self, buf: &Buffer) -> (usize, usize) {
+        let (start, end) = self.ordered();
+        (buf.rope.char_to_line(start), buf.rope.char_to_line(end))
+    }
+
+    // Chars covered by a charwise or linewise selection, and whether
+    // it is made of whole lines
+    pub fn range(&self, buf: &Buffer) -> (Range<usize>, bool) {
+        if self.kind == Kind::Line {
+            let (first, last) = self.lines(buf);
+            return (motion::line_range(buf, first, last), true);
+        }
+        let (start, end) = self.ordered();
+        (start..(end + 1).min(buf.rope.len_chars()), false)
+    }
+
+    // Screen columns of a block, both inclusive
+    pub fn columns(&self, buf: &Buffer, tab_width: usize) -> (usize, usize) {
+        let a = motion::col_at_char(buf, self.anchor, tab_width);
+        let h = motion::col_at_char(buf, self.head, tab_width);
+        (a.min(h), a.max(h))
+    }
+
+    // Chars selected on `line`, None when nothing is
+    pub fn span(&self, buf: &Buffer, line: usize, tab_width: usize) -> Option<Range<usize>> {
+        let (first, last) = self.lines(buf);
+        if line < first || line > last {
+            return None;
+        }
+        let ls = motion::line_start(buf, line);
+        let le = ls + buf.line_len(line);
+
+        match self.kind {
+            Kind::Line => Some(ls..le),
+            Kind::Char => {
+                let (start, end) = self.ordered();
+                Some(start.max(ls)..(end + 1).min(le).max(start.max(ls)))
+            }
+            Kind::Block => {
+                let (left, right) = self.columns(buf, tab_width);
+                let start = motion::char_at_col(buf, line, left, tab_width);
+                // Lines too short to reach the block
+                if start == le && motion::col_at_char(buf, le, tab_width) < left {
+                    return None;
+                }
+                let end = if self.to_end {
+                    le
+                } else {
+                    (motion::char_at_col(buf, line, right, tab_width) + 1).min(le)
+                };
+                Some(start..end.max(start))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sel(kind: Kind, anchor: usize, head: usize) -> Selection {
+        Selection {
+            head,
+            ..Selection::new(kind, anchor)
+        }
+    }
+
+    fn spans(buf: &Buffer, s: &Selection) -> Vec<Option<Range<usize>>> {
+        (0..buf.line_count).map(|l| s.span(buf, l, 4)).collect()
+    }
+
+    // a0 b1 c2 d4 e6 f7 g8
+    const TEXT: &str = "abc\nd\nefg\n";
+
+    #[test]
+    fn chars() {
+        let buf = Buffer::new().set_text(TEXT);
+        for s in &[sel(Kind::Char, 1, 4), sel(Kind::Char, 4, 1)] {
+            assert_eq!(s.range(&buf), (1..5, false));
+            assert_eq!(spans(&buf, s), vec![Some(1..3), Some(4..5), None, None]);
+        }
+        // Up to the end of the buffer
+        assert_eq!(sel(Kind::Char, 8, 9).range(&buf), (8..10, false));
+        // An empty line shows as an empty span
+        let buf = Buffer::new().set_text("a\n\nb\n");
+        assert_eq!(
+            spans(&buf, &sel(Kind::Char, 0, 3)),
+            vec![Some(0..1), Some(2..2), Some(3..4), None]
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let buf = Buffer::new().set_text(TEXT);
+        let s = sel(Kind::Line, 5, 1);
+        assert_eq!(s.range(&buf), (0..6, true));
+        assert_eq!(spans(&buf, &s), vec![Some(0..3), Some(4..5), None, None]);
+        // The last line without a line ending
+        let buf = Buffer::new().set_text("a\nb");
+        assert_eq!(sel(Kind::Line, 2, 2).range(&buf), (2..3, true));
+    }
+
+    #[test]
+    fn blocks() {
+        let buf = Buffer::new().set_text(TEXT);
+        let s = sel(Kind::Block, 1, 7);
+        assert_eq!(s.columns(&buf, 4), (1, 1));
+        // A line ending right at the block has an empty row in it
+        assert_eq!(
+            spans(&buf, &s),
+            vec![Some(1..2), Some(5..5), Some(7..8), None]
+        );
+        // Either corner
+        let s = sel(Kind::Block, 8, 0);
+        assert_eq!(
+            spans(&buf, &s),
+            vec![Some(0..3), Some(4..5), Some(6..9), None]
+        );
+        // After `$` every line to its end
+        let s = Selection {
+            to_end: true,
+            ..sel(Kind::Block, 1, 7)
+        };
+        assert_eq!(
+            spans(&buf, &s),
+            vec![Some(1..3), Some(5..5), Some(7..9), None]
+        );
+        // A tab in the block is taken whole
+        let buf = Buffer::new().set_text("abcdef\n\tx\n");
+        let s = sel(Kind::Block, 3, 7);
+        assert_eq!(s.columns(&buf, 4), (0, 3));
+        assert_eq!(spans(&buf, &s)[..2], [Some(0..4), Some(7..8)]);
+    }
+}
@@ -0,0 +1,586 @@
+// Text objects, the `iw` in `diw`
+// Each one selects a range around the cursor, `inner` leaves out the
+// surrounding space or delimiters
+use super::buffer::{Buffer, Language};
+use super::motion::{self, class, CharClass};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextObject {
+    // `true` for WORD
+    Word(bool),
+    Sentence,
+    Paragraph,
+    Quote(char),
+    Pair(char, char),
+    // A C function, from its signature to the closing brace
+    Function,
+}
+
+impl TextObject {
+    // The char typed after `i` or `a`
+    pub fn from_char(c: char) -> Option<TextObject> {
+        let obj = match c {
+            'w' => TextObject::Word(false),
+            'W' => TextObject::Word(true),
+            's' => TextObject::Sentence,
+            'p' => TextObject::Paragraph,
+            '"' | '\'' | '`' => TextObject::Quote(c),
+            '(' | ')' | 'b' => TextObject::Pair('(', ')'),
+            '{' | '}' | 'B' => TextObject::Pair('{', '}'),
+            '[' | ']' => TextObject::Pair('[', ']'),
+            '<' | '>' => TextObject::Pair('<', '>'),
+            'f' => TextObject::Function,
+            _ => return None,
+        };
+        Some(obj)
+    }
+
+    // Range around `idx` and whether it is made of whole lines
+    // None when the cursor isn't in such an object
+    pub fn range(
+        self,
+        buf: &Buffer,
+        idx: usize,
+        inner: bool,
+        count: usize,
+    ) -> Option<(Range<usize>, bool)> {
+        let len = buf.rope.len_chars();
+        if len == 0 {
+            return None;
+        }
+        let idx = idx.min(len - 1);
+
+        match self {
+            TextObject::Word(big) => word(buf, idx, inner, count, big).map(|r| (r, false)),
+            TextObject::Sentence => sentence(buf, idx, inner).map(|r| (r, false)),
+            TextObject::Paragraph => Some(paragraph(buf, idx, inner, count)),
+            TextObject::Quote(q) => quote(buf, idx, inner, q).map(|r| (r, false)),
+            TextObject::Pair(open, close) => {
+                let (o, c) = pair(buf, idx, open, close, count)?;
+                if inner {
+                    Some(inner_block(buf, o, c))
+                } else {
+                    Some((o..c + 1, false))
+                }
+            }
+            TextObject::Function => {
+                if !matches!(buf.lang, Language::C | Language::Cpp) {
+                    return None;
+                }
+                function(buf, idx, inner)
+            }
+        }
+    }
+}
+
+fn is_space(buf: &Buffer, i: usize) -> bool {
+    class(buf.rope.char(i), false) == CharClass::Space
+}
+
+// One past the run of same class chars at `i`, stopping at `end`
+fn run_end(buf: &Buffer, i: usize, end: usize, big: bool) -> usize {
+    let c = class(buf.rope.char(i), big);
+    let mut j = i;
+    while j < end && class(buf.rope.char(j), big) == c {
+        j += 1;
+    }
+    j
+}
+
+// iw and aw, they don't leave the line
+fn word(buf: &Buffer, idx: usize, inner: bool, count: usize, big: bool) -> Option<Range<usize>> {
+    let line = buf.rope.char_to_line(idx);
+    let ls = motion::line_start(buf, line);
+    let le = ls + buf.line_len(line);
+    if ls == le {
+        return None;
+    }
+    let idx = idx.min(le - 1);
+
+    let c = class(buf.rope.char(idx), big);
+    let mut start = idx;
+    while start > ls && class(buf.rope.char(start - 1), big) == c {
+        start -= 1;
+    }
+    let mut end = run_end(buf, idx, le, big);
+
+    if inner {
+        // Every count takes the next word or the space between
+        for _ in 1..count {
+            if end < le {
+                end = run_end(buf, end, le, big);
+            }
+        }
+    } else if c == CharClass::Space {
+        // The space and the word after it
+        for n in 0..count {
+            if n > 0 && end < le {
+                end = run_end(buf, end, le, big);
+            }
+            if end < le {
+                end = run_end(buf, end, le, big);
+            }
+        }
+    } else {
+        // The word and the space after it
+        for n in 0..count {
+            if n > 0 && end < le {
+                end = run_end(buf, end, le, big);
+            }
+            if end < le && is_space(buf, end) {
+                end = run_end(buf, end, le, big);
+            }
+        }
+        // At the end of the line take the space before instead
+        if !is_space(buf, end - 1) {
+            while start > ls && is_space(buf, start - 1) {
+                start -= 1;
+            }
+        }
+    }
+    Some(start..end)
+}
+
+// is and as, sentences end at . ! or ? and don't leave the paragraph
+fn sentence(buf: &Buffer, idx: usize, inner: bool) -> Option<Range<usize>> {
+    let line = buf.rope.char_to_line(idx);
+    if buf.line_len(line) == 0 {
+        return None;
+    }
+    let mut first = line;
+    while first > 0 && buf.line_len(first - 1) != 0 {
+        first -= 1;
+    }
+    let mut last = line;
+    while last + 1 < buf.line_count && buf.line_len(last + 1) != 0 {
+        last += 1;
+    }
+    let ps = motion::line_start(buf, first);
+    let pe = motion::line_start(buf, last) + buf.line_len(last);
+    let chars: Vec<char> = buf.rope.slice(ps..pe).chars().collect();
+    let n = chars.len();
+
+    // (start, end, end with the space after it)
+    let mut sentences = Vec::new();
+    let mut i = 0;
+    while i < n && chars[i].is_whitespace() {
+        i += 1;
+    }
+    while i < n {
+        let s = i;
+        while i < n {
+            if let '.' | '!' | '?' = chars[i] {
+                let mut j = i + 1;
+                while j < n && matches!(chars[j], ')' | ']' | '"' | '\'') {
+                    j += 1;
+                }
+                if j == n || chars[j].is_whitespace() {
+                    i = j;
+                    break;
+                }
+            }
+            i += 1;
+        }
+        let e = i;
+        while i < n && chars[i].is_whitespace() {
+            i += 1;
+        }
+        sentences.push((s, e, i));
+    }
+
+    let col = idx - ps;
+    let pos = sentences.iter().position(|(_, _, t)| col < *t)?;
+    let (mut s, e, t) = sentences[pos];
+    if inner {
+        return Some(ps + s..ps + e);
+    }
+    // The last sentence takes the space before it instead
+    if t == e && pos > 0 {
+        s = sentences[pos - 1].1;
+    }
+    Some(ps + s..ps + t)
+}
+
+// ip and ap, a run of non empty lines or a run of empty ones
+fn paragraph(buf: &Buffer, idx: usize, inner: bool, count: usize) -> (Range<usize>, bool) {
+    // Not the empty line after the final line ending
+    let mut last_line = buf.line_count.saturating_sub(1);
+    if last_line > 0 && buf.line_len(last_line) == 0 {
+        last_line -= 1;
+    }
+    let blank = |l: usize| buf.line_len(l) == 0;
+    // Last line of the run starting at `l`
+    let run_end = |l: usize| {
+        let mut e = l;
+        while e < last_line && blank(e + 1) == blank(l) {
+            e += 1;
+        }
+        e
+    };
+
+    let line = buf.rope.char_to_line(idx);
+    let mut first = line;
+    while first > 0 && blank(first - 1) == blank(line) {
+        first -= 1;
+    }
+    let mut last = run_end(line);
+    // `ap` takes the run after each paragraph as well
+    let runs = if inner { count } else { count * 2 };
+    for _ in 1..runs {
+        if last < last_line {
+            last = run_end(last + 1);
+        }
+    }
+    // With no empty lines after it, `ap` takes the ones before
+    if !inner && !blank(line) && !blank(last) {
+        while first > 0 && blank(first - 1) {
+            first -= 1;
+        }
+    }
+    (motion::line_range(buf, first, last), true)
+}
+
+// i" and a", quotes are paired from the start of the line
+fn quote(buf: &Buffer, idx: usize, inner: bool, q: char) -> Option<Range<usize>> {
+    let line = buf.rope.char_to_line(idx);
+    let ls = motion::line_start(buf, line);
+    let chars: Vec<char> = buf
+        .rope
+        .line(line)
+        .chars()
+        .take(buf.line_len(line))
+        .collect();
+    let col = idx - ls;
+
+    let mut quotes = Vec::new();
+    for (i, c) in chars.iter().enumerate() {
+        if *c == q && (i == 0 || chars[i - 1] != '\\') {
+            quotes.push(i);
+        }
+    }
+    let (a, b) = quotes
+        .chunks(2)
+        .filter(|p| p.len() == 2)
+        .map(|p| (p[0], p[1]))
+        .find(|(a, b)| col <= *b || *a > col)?;
+
+    if inner {
+        return Some(ls + a + 1..ls + b);
+    }
+    let (mut start, mut end) = (a, b + 1);
+    if end < chars.len() && chars[end].is_whitespace() {
+        while end < chars.len() && chars[end].is_whitespace() {
+            end += 1;
+        }
+    } else {
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+    }
+    Some(ls + start..ls + end)
+}
+
+// Positions of the `count`th pair of brackets around `idx`
+fn pair(buf: &Buffer, idx: usize, open: char, close: char, count: usize) -> Option<(usize, usize)> {
+    let mut o = idx + 1;
+    for _ in 0..count {
+        // A closing bracket under the cursor belongs to its own pair
+        let mut depth = 0;
+        let mut i = o;
+        o = loop {
+            i = i.checked_sub(1)?;
+            let c = buf.rope.char(i);
+            if c == close && i != idx {
+                depth += 1;
+            } else if c == open {
+                if depth == 0 {
+                    break i;
+                }
+                depth -= 1;
+            }
+        };
+    }
+
+    let mut depth = 0;
+    for (i, c) in buf.rope.chars_at(o + 1).enumerate() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return Some((o, o + 1 + i));
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+// Inside of a bracket pair. When the brackets sit on their own lines
+// this is the lines between them.
+fn inner_block(buf: &Buffer, open: usize, close: usize) -> (Range<usize>, bool) {
+    let open_line = buf.rope.char_to_line(open);
+    let close_line = buf.rope.char_to_line(close);
+    if open_line < close_line {
+        let after_open = motion::line_start(buf, open_line) + buf.line_len(open_line);
+        let rest_blank = (open + 1..after_open).all(|i| is_space(buf, i));
+        let close_first = motion::first_non_blank(buf, close_line) == close;
+        if rest_blank && close_first {
+            if close_line == open_line + 1 {
+                return (after_open..after_open, false);
+            }
+            return (motion::line_range(buf, open_line + 1, close_line - 1), true);
+        }
+    }
+    (open + 1..close, false)
+}
+
+// if and af. A function is a top level `{ }` block whose header ends in `)`.
+// Comments before the header are left out, ones inside it are skipped.
+fn function(buf: &Buffer, idx: usize, inner: bool) -> Option<(Range<usize>, bool)> {
+    #[derive(PartialEq)]
+    enum State {
+        Code,
+        LineComment,
+        BlockComment,
+        Preprocessor,
+        Literal(char),
+    }
+
+    let mut state = State::Code;
+    let mut prev = '\n';
+    let mut depth = 0;
+    // First and last code char before the next top level block
+    let mut header: Option<usize> = None;
+    let mut last_code = 0;
+    let mut open = 0;
+    let mut blocks = Vec::new();
+
+    for (i, c) in buf.rope.chars().enumerate() {
+        match state {
+            State::Code => {
+                // A `/` is code once the char after it doesn't start a comment
+                if prev == '/' && c != '/' && c != '*' && depth == 0 {
+                    header.get_or_insert(i - 1);
+                    last_code = i - 1;
+                }
+                match c {
+                    '/' if prev == '/' => state = State::LineComment,
+                    '*' if prev == '/' => {
+                        state = State::BlockComment;
+                        // `/*/` doesn't end it
+                        prev = ' ';
+                        continue;
+                    }
+                    '#' if depth == 0 && prev == '\n' => state = State::Preprocessor,
+                    '{' => {
+                        if depth == 0 {
+                            open = i;
+                        }
+                        depth += 1;
+                    }
+                    '}' if depth > 0 => {
+                        depth -= 1;
+                        if depth == 0 {
+                            if let Some(h) = header.take() {
+                                blocks.push((h, last_code, open, i));
+                            }
+                        }
+                    }
+                    ';' if depth == 0 => header = None,
+                    '/' => (),
+                    _ => {
+                        if let '"' | '\'' = c {
+                            state = State::Literal(c);
+                        }
+                        if depth == 0 && !c.is_whitespace() {
+                            header.get_or_insert(i);
+                            last_code = i;
+                        }
+                    }
+                }
+            }
+            State::LineComment | State::Preprocessor => {
+                if c == '\n' {
+                    if state == State::Preprocessor && depth == 0 {
+                        header = None;
+                    }
+                    state = State::Code;
+                }
+            }
+            State::BlockComment => {
+                if c == '/' && prev == '*' {
+                    state = State::Code;
+                    // The `/` ending it isn't code or the start of another comment
+                    prev = ' ';
+                    continue;
+                }
+            }
+            State::Literal(q) => {
+                if c == q && prev != '\\' {
+                    state = State::Code;
+                }
+            }
+        }
+        // An escaped backslash doesn't escape the quote after it
+        prev = if prev == '\\' && c == '\\' { ' ' } else { c };
+    }
+
+    let (start, _, o, c) = blocks
+        .into_iter()
+        .find(|(h, l, _, c)| buf.rope.char(*l) == ')' && *h <= idx && idx <= *c)?;
+
+    if inner {
+        return Some(inner_block(buf, o, c));
+    }
+
+    let first = buf.rope.char_to_line(start);
+    let mut last = buf.rope.char_to_line(c);
+    // Blank lines after it go too, like `ap`
+    while last + 1 < buf.line_count && buf.line_len(last + 1) == 0 {
+        last += 1;
+    }
+    Some((motion::line_range(buf, first, last), true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text `obj` covers from `idx`, and whether it's linewise
+    fn select(
+        text: &str,
+        obj: char,
+        idx: usize,
+        inner: bool,
+        count: usize,
+    ) -> Option<(String, bool)> {
+        let mut buf = Buffer::new().set_text(text);
+        buf.lang = Language::C;
+        let (range, linewise) = TextObject::from_char(obj)?.range(&buf, idx, inner, count)?;
+        Some((buf.rope.slice(range).to_string(), linewise))
+    }
+
+    fn chars(text: &str, obj: char, idx: usize, inner: bool, count: usize) -> String {
+        select(text, obj, idx, inner, count).unwrap().0
+    }
+
+    #[test]
+    fn words() {
+        let text = "foo.bar  baz\n";
+        assert_eq!(chars(text, 'w', 1, true, 1), "foo");
+        assert_eq!(chars(text, 'w', 1, true, 2), "foo.");
+        assert_eq!(chars(text, 'W', 1, true, 1), "foo.bar");
+        // The space after it, or before it at the end of the line
+        assert_eq!(chars(text, 'W', 1, false, 1), "foo.bar  ");
+        assert_eq!(chars(text, 'w', 10, false, 1), "  baz");
+        // On space, the space and the word after it
+        assert_eq!(chars(text, 'w', 7, true, 1), "  ");
+        assert_eq!(chars(text, 'w', 7, false, 1), "  baz");
+        // Not past the line
+        assert_eq!(chars(text, 'w', 10, true, 5), "baz");
+        assert_eq!(select("a\n\nb\n", 'w', 2, true, 1), None);
+    }
+
+    #[test]
+    fn pairs() {
+        let text = "f(a, (b), c)\n";
+        assert_eq!(chars(text, '(', 3, true, 1), "a, (b), c");
+        assert_eq!(chars(text, ')', 6, true, 1), "b");
+        assert_eq!(chars(text, 'b', 6, false, 2), "(a, (b), c)");
+        // On a bracket, its own pair
+        assert_eq!(chars(text, '(', 7, false, 1), "(b)");
+        assert_eq!(chars(text, '(', 5, false, 1), "(b)");
+        assert_eq!(select(text, '(', 0, true, 1), None);
+        assert_eq!(select(text, '(', 3, true, 3), None);
+        // Brackets on their own lines, the lines between them
+        let text = "if (x) {\n  a;\n  b;\n}\n";
+        assert_eq!(
+            select(text, '{', 11, true, 1),
+            Some(("  a;\n  b;\n".to_string(), true))
+        );
+        assert_eq!(chars("{\n}\n", '{', 0, true, 1), "");
+    }
+
+    #[test]
+    fn quotes() {
+        let text = r#"say "hi \"you\"" and "bye""#;
+        assert_eq!(chars(text, '"', 6, true, 1), r#"hi \"you\""#);
+        assert_eq!(chars(text, '"', 6, false, 1), r#""hi \"you\"" "#);
+        // Before the first quote, the first pair
+        assert_eq!(chars(text, '"', 0, true, 1), r#"hi \"you\""#);
+        // The last pair takes the space before it
+        assert_eq!(chars(text, '"', 23, false, 1), r#" "bye""#);
+        // Between two pairs is outside of both
+        assert_eq!(chars(text, '"', 18, true, 1), "bye");
+        assert_eq!(select("'a' b\n", '"', 0, true, 1), None);
+    }
+
+    #[test]
+    fn paragraphs() {
+        let text = "a\nb\n\n\nc\n";
+        assert_eq!(
+            select(text, 'p', 0, true, 1),
+            Some(("a\nb\n".to_string(), true))
+        );
+        assert_eq!(chars(text, 'p', 0, false, 1), "a\nb\n\n\n");
+        assert_eq!(chars(text, 'p', 4, true, 1), "\n\n");
+        assert_eq!(chars(text, 'p', 4, false, 1), "\n\nc\n");
+        assert_eq!(chars(text, 'p', 0, true, 2), "a\nb\n\n\n");
+        // No empty lines after the last one, `ap` takes the ones before
+        assert_eq!(chars(text, 'p', 6, false, 1), "\n\nc\n");
+        assert_eq!(chars("a\n\nb", 'p', 3, false, 1), "\nb");
+        assert_eq!(chars(text, 'p', 0, false, 9), text);
+    }
+
+    #[test]
+    fn functions() {
+        let text = "#include <a.h>\n\
+                    int x = 1;\n\
+                    \n\
+                    /* doc */\n\
+                    int f(void) // why\n\
+                    {\n\
+                    \treturn g(\"}\");\n\
+                    }\n\
+                    \n\
+                    static int h(int a) /* x */ {\n\
+                    \tif (a) { return 1; }\n\
+                    \treturn a / 2;\n\
+                    }\n";
+        let f = text.find("int f").unwrap();
+        let h = text.find("static").unwrap();
+        let f_body = text.find("\treturn g").unwrap();
+        let h_body = text.find("\tif").unwrap();
+
+        let whole_f = "int f(void) // why\n{\n\treturn g(\"}\");\n}\n\n";
+        assert_eq!(
+            select(text, 'f', f_body, false, 1),
+            Some((whole_f.to_string(), true))
+        );
+        assert_eq!(
+            select(text, 'f', f + 2, false, 1),
+            Some((whole_f.to_string(), true))
+        );
+        assert_eq!(
+            select(text, 'f', f_body, true, 1),
+            Some(("\treturn g(\"}\");\n".to_string(), true))
+        );
+        assert_eq!(chars(text, 'f', h_body, false, 1), &text[h..]);
+        assert_eq!(
+            chars(text, 'f', h, true, 1),
+            "\tif (a) { return 1; }\n\treturn a / 2;\n"
+        );
+        // Outside of any function
+        assert_eq!(select(text, 'f', 0, false, 1), None);
+        assert_eq!(select(text, 'f', text.find("doc").unwrap(), false, 1), None);
+        assert_eq!(
+            select("struct s {\n\tint a;\n};\n", 'f', 12, false, 1),
+            None
+        );
+
+        // Only in C and C++
+        let buf = Buffer::new().set_text(text);
+        assert_eq!(TextObject::Function.range(&buf, f_body, false, 1), None);
+    }
+}
@@ -0,0 +1,489 @@
+// Undo/Redo history for a Buffer
+// Every edit is stored as a Change, and Changes are grouped into
+// Transactions, which are what `u` and `Ctrl-r` step through.
+// History can be written to an undo file and read back later.
+use std::str;
+
+const UNDO_MAGIC: &str = "zed-undo 1";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Insert { at: usize, text: String },
+    Delete { at: usize, text: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transaction {
+    pub changes: Vec<Change>,
+    // Cursor (as a char index) before and after the transaction
+    pub cursor_before: usize,
+    pub cursor_after: usize,
+}
+
+impl Transaction {
+    pub fn new(cursor: usize) -> Transaction {
+        Transaction {
+            changes: Vec::new(),
+            cursor_before: cursor,
+            cursor_after: cursor,
+        }
+    }
+
+    // Merge typing into the previous change where possible,
+    // so an insert session is not stored char by char
+    pub fn push(&mut self, change: Change) {
+        if let Some(last) = self.changes.last_mut() {
+            match (last, &change) {
+                (
+                    Change::Insert { at, text },
+                    Change::Insert {
+                        at: new_at,
+                        text: new_text,
+                    },
+                ) if *at + text.chars().count() == *new_at => {
+                    text.push_str(new_text);
+                    return;
+                }
+                (
+                    Change::Delete { at, text },
+                    Change::Delete {
+                        at: new_at,
+                        text: new_text,
+                    },
+                ) if *new_at + new_text.chars().count() == *at => {
+                    // Backspacing
+                    text.insert_str(0, new_text);
+                    *at = *new_at;
+                    return;
+                }
+                _ => (),
+            }
+        }
+        self.changes.push(change);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    pub undo: Vec<Transaction>,
+    pub redo: Vec<Transaction>,
+    pending: Option<Transaction>,
+    // Undo depth when the text last matched the file, None once that
+    // state was thrown away with the redo stack
+    saved: Option<usize>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            pending: None,
+            saved: Some(0),
+        }
+    }
+
+    // The text matches the file now
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    // Back where it was at `mark_saved`, by undo and redo or not moving at all
+    pub fn is_saved(&self) -> bool {
+        let typing = matches!(&self.pending, Some(t) if !t.changes.is_empty());
+        !typing && self.saved == Some(self.undo.len())
+    }
+
+    // Start grouping changes. Does nothing if a transaction is already open.
+    pub fn begin(&mut self, cursor: usize) {
+        if self.pending.is_none() {
+            self.pending = Some(Transaction::new(cursor));
+        }
+    }
+
+    // Close the open transaction, dropping it if nothing changed
+    pub fn commit(&mut self, cursor: usize) {
+        if let Some(mut t) = self.pending.take() {
+            if !t.changes.is_empty() {
+                t.cursor_after = cursor;
+                self.undo.push(t);
+            }
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Record a change. Changes made outside a transaction are their own step.
+    pub fn record(&mut self, change: Change) {
+        if matches!(self.saved, Some(s) if s > self.undo.len()) {
+            self.saved = None;
+        }
+        self.redo.clear();
+        match &mut self.pending {
+            Some(t) => t.push(change),
+            None => {
+                let at = match &change {
+                    Change::Insert { at, .. } | Change::Delete { at, .. } => *at,
+                };
+                let mut t = Transaction::new(at);
+                t.push(change);
+                t.cursor_after = at;
+                self.undo.push(t);
+            }
+        }
+    }
+
+    // Undo file format:
+    //   zed-undo 1
+    //   hash <content hash>
+    //   u|r <cursor before> <cursor after> <change count>
+    //   i|d <at> <byte length>
+    //   <text>
+    pub fn serialize(&self, hash: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(format!("{}\nhash {:016x}\n", UNDO_MAGIC, hash).as_bytes());
+
+        let stacks = [("u", &self.undo), ("r", &self.redo)];
+        for (tag, stack) in stacks.iter() {
+            for t in stack.iter() {
+                let header = format!(
+                    "{} {} {} {}\n",
+                    tag,
+                    t.cursor_before,
+                    t.cursor_after,
+                    t.changes.len()
+                );
+                out.extend_from_slice(header.as_bytes());
+                for change in t.changes.iter() {
+                    let (c, at, text) = match change {
+                        Change::Insert { at, text } => ("i", at, text),
+                        Change::Delete { at, text } => ("d", at, text),
+                    };
+                    out.extend_from_slice(format!("{} {} {}\n", c, at, text.len()).as_bytes());
+                    out.extend_from_slice(text.as_bytes());
+                    out.push(b'\n');
+                }
+            }
+        }
+        out
+    }
+
+    // Returns the content hash the history belongs to, and the history
+    pub fn deserialize(data: &[u8]) -> Option<(u64, History)> {
+        let mut history = History::new();
+        let mut pos = 0;
+
+        if next_line(data, &mut pos)? != UNDO_MAGIC {
+            return None;
+        }
+        let hash_line = next_line(data, &mut pos)?;
+        let hash = u64::from_str_radix(hash_line.strip_prefix("hash ")?, 16).ok()?;
+
+        while pos < data.len() {
+            let header = next_line(data, &mut pos)?;
+            let fields: Vec<&str> = header.split(' ').collect();
+            if fields.len() != 4 {
+                return None;
+            }
+            let mut t = Transaction::new(fields[1].parse().ok()?);
+            t.cursor_after = fields[2].parse().ok()?;
+
+            for _ in 0..fields[3].parse::<usize>().ok()? {
+                let change = next_line(data, &mut pos)?;
+                let fields: Vec<&str> = change.split(' ').collect();
+                if fields.len() != 3 {
+                    return None;
+                }
+                let at = fields[1].parse().ok()?;
+                let len: usize = fields[2].parse().ok()?;
+                let text = str::from_utf8(data.get(pos..pos + len)?).ok()?.to_string();
+                pos += len + 1;
+
+                t.changes.push(match fields[0] {
+                    "i" => Change::Insert { at, text },
+                    "d" => Change::Delete { at, text },
+                    _ => return None,
+                });
+            }
+
+            match fields[0] {
+                "u" => history.undo.push(t),
+                "r" => history.redo.push(t),
+                _ => return None,
+            }
+        }
+        Some((hash, history))
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Transaction> {
+        let t = self.undo.pop()?;
+        self.redo.push(t.clone());
+        Some(t)
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Transaction> {
+        let t = self.redo.pop()?;
+        self.undo.push(t.clone());
+        Some(t)
+    }
+}
+
+fn next_line<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a str> {
+    let rest = data.get(*pos..)?;
+    let end = rest.iter().position(|&b| b == b'\n')?;
+    *pos += end + 1;
+    str::from_utf8(&rest[..end]).ok()
+}
+
+// FNV-1a, stable across builds unlike std's hasher
+pub fn hash_bytes<'a, I: IntoIterator<Item = &'a [u8]>>(chunks: I) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for chunk in chunks {
+        for b in chunk {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> History {
+        let mut h = History::new();
+        h.begin(0);
+        h.record(Change::Insert {
+            at: 0,
+            text: "héllo\nworld".to_string(),
+        });
+        h.commit(11);
+        // Text with newlines, spaces and multi-byte chars, lengths are in bytes
+        h.record(Change::Delete {
+            at: 3,
+            text: "lo\n wö".to_string(),
+        });
+        h.begin(5);
+        h.record(Change::Insert {
+            at: 5,
+            text: String::new(),
+        });
+        h.record(Change::Delete {
+            at: 0,
+            text: "\n\n".to_string(),
+        });
+        h.commit(0);
+        h.pop_undo();
+        h
+    }
+
+    #[test]
+    fn round_trip() {
+        let h = history();
+        assert_eq!(h.undo.len(), 2);
+        assert_eq!(h.redo.len(), 1);
+
+        let (hash, back) = History::deserialize(&h.serialize(0xdead_beef)).unwrap();
+        assert_eq!(hash, 0xdead_beef);
+        assert_eq!(back.undo, h.undo);
+        assert_eq!(back.redo, h.redo);
+        assert!(!back.in_transaction());
+    }
+
+    #[test]
+    fn empty_history() {
+        let (hash, back) = History::deserialize(&History::new().serialize(u64::MAX)).unwrap();
+        assert_eq!(hash, u64::MAX);
+        assert!(back.undo.is_empty() && back.redo.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let data = history().serialize(1);
+        assert!(History::deserialize(b"").is_none());
+        assert!(History::deserialize(b"zed-undo 0\nhash 1\n").is_none());
+        assert!(History::deserialize(b"zed-undo 1\nhash xyz\n").is_none());
+        // Cut off in the middle of a change's text
+        assert!(History::deserialize(&data[..data.len() - 3]).is_none());
+        let mut bad = data.clone();
+        bad.extend_from_slice(b"x 0 0 0\n");
+        assert!(History::deserialize(&bad).is_none());
+    }
+
+    #[test]
+    fn merges_typing_and_backspacing() {
+        let mut t = Transaction::new(0);
+        t.push(Change::Insert {
+            at: 0,
+            text: "ab".to_string(),
+        });
+        t.push(Change::Insert {
+            at: 2,
+            text: "c".to_string(),
+        });
+        t.push(Change::Delete {
+            at: 2,
+            text: "c".to_string(),
+        });
+        t.push(Change::Delete {
+            at: 1,
+            text: "b".to_string(),
+        });
+        assert_eq!(
+            t.changes,
+            vec![
+                Change::Insert {
+                    at: 0,
+                    text: "abc".to_string()
+                },
+                Change::Delete {
+                    at: 1,
+                    text: "bc".to_string()
+                },
+            ]
+        );
+    }
+
+    fn insert(h: &mut History, at: usize) {
+        h.record(Change::Insert {
+            at,
+            text: "x".to_string(),
+        });
+    }
+
+    #[test]
+    fn saved_position() {
+        let mut h = History::new();
+        assert!(h.is_saved());
+        insert(&mut h, 0);
+        insert(&mut h, 5);
+        h.mark_saved();
+        assert!(h.is_saved());
+
+        h.pop_undo();
+        assert!(!h.is_saved());
+        h.pop_redo();
+        assert!(h.is_saved());
+
+        // Typing counts before it's committed
+        h.begin(0);
+        assert!(h.is_saved());
+        insert(&mut h, 1);
+        assert!(!h.is_saved());
+        h.commit(2);
+        h.pop_undo();
+        assert!(h.is_saved());
+    }
+
+    #[test]
+    fn saved_position_thrown_away() {
+        let mut h = History::new();
+        insert(&mut h, 0);
+        insert(&mut h, 1);
+        h.mark_saved();
+        h.pop_undo();
+        h.pop_undo();
+        // A new change drops the redo stack with the saved state on it
+        insert(&mut h, 0);
+        insert(&mut h, 1);
+        assert_eq!(h.undo.len(), 2);
+        assert!(!h.is_saved());
+
+        // Changes on top of the saved state keep it
+        h.mark_saved();
+        insert(&mut h, 2);
+        h.pop_undo();
+        assert!(h.is_saved());
+    }
+
+    #[test]
+    fn transactions_group_changes() {
+        let mut h = History::new();
+        assert!(!h.in_transaction());
+        h.begin(3);
+        assert!(h.in_transaction());
+        // Nested begins join the open one
+        h.begin(9);
+        insert(&mut h, 3);
+        insert(&mut h, 4);
+        h.record(Change::Delete {
+            at: 0,
+            text: "a".to_string(),
+        });
+        assert!(h.undo.is_empty());
+        h.commit(4);
+        assert!(!h.in_transaction());
+
+        assert_eq!(h.undo.len(), 1);
+        let t = &h.undo[0];
+        assert_eq!((t.cursor_before, t.cursor_after), (3, 4));
+        assert_eq!(t.changes.len(), 2);
+
+        // Nothing changed, nothing to undo
+        h.begin(0);
+        h.commit(5);
+        assert_eq!(h.undo.len(), 1);
+    }
+
+    #[test]
+    fn changes_outside_a_transaction() {
+        let mut h = History::new();
+        insert(&mut h, 7);
+        insert(&mut h, 8);
+        assert_eq!(h.undo.len(), 2);
+        assert_eq!((h.undo[1].cursor_before, h.undo[1].cursor_after), (8, 8));
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut h = History::new();
+        assert!(h.pop_undo().is_none());
+        insert(&mut h, 0);
+        insert(&mut h, 1);
+
+        let t = h.pop_undo().unwrap();
+        assert_eq!(t.cursor_before, 1);
+        assert_eq!((h.undo.len(), h.redo.len()), (1, 1));
+        h.pop_undo();
+        assert!(h.pop_undo().is_none());
+        assert_eq!(h.redo.len(), 2);
+
+        // Redo goes back up in order
+        assert_eq!(h.pop_redo().unwrap().cursor_after, 0);
+        assert_eq!(h.pop_redo().unwrap().cursor_after, 1);
+        assert!(h.pop_redo().is_none());
+
+        // A new change ends the redo stack
+        h.pop_undo();
+        insert(&mut h, 5);
+        assert!(h.redo.is_empty());
+        assert!(h.pop_redo().is_none());
+        assert_eq!(h.undo.len(), 2);
+    }
+
+    #[test]
+    fn buffer_undo_restores_the_cursor() {
+        use crate::backend::buffer::Buffer;
+
+        let mut buf = Buffer::new().set_text("one two\n");
+        buf.begin_transaction(4);
+        buf.delete(4..7);
+        buf.insert_str(4, "three");
+        buf.commit_transaction(8);
+        buf.delete(0..4);
+        assert_eq!(buf.rope.to_string(), "three\n");
+
+        assert_eq!(buf.undo(), Some(0));
+        assert_eq!(buf.undo(), Some(4));
+        assert_eq!(buf.rope.to_string(), "one two\n");
+        assert_eq!(buf.undo(), None);
+        assert_eq!(buf.redo(), Some(8));
+        assert_eq!(buf.rope.to_string(), "one three\n");
+        assert_eq!(buf.redo(), Some(0));
+        assert_eq!(buf.redo(), None);
+        assert_eq!(buf.rope.to_string(), "three\n");
+    }
+}
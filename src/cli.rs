@@ -9,6 +9,8 @@ use zui_core::term::Terminal;
 #[derive(Debug)]
 pub enum Target {
     File(PathBuf),
+    // A file and a 1-based line to start on
+    Line(PathBuf, usize),
     Dir(PathBuf),
     Empty,
 }
@@ -19,10 +21,13 @@ pub struct Cli {
     pub target: Target,
 
     // Should backup file on save
-    backup: bool,
+    pub backup: bool,
 
     // Should save on exit
-    save_on_exit: bool,
+    pub save_on_exit: bool,
+
+    // Should copy yanks to the terminal clipboard
+    pub clipboard: bool,
 
     // Location of (custom) config file
     pub config: Option<PathBuf>,
@@ -36,6 +41,7 @@ impl Cli {
             target: Target::Empty,
             backup: false,
             save_on_exit: false,
+            clipboard: false,
             config: Some(config),
         }
     }
@@ -45,6 +51,10 @@ impl Cli {
         for (i, arg) in args.iter().enumerate().skip(1) {
             if arg == &String::from("-b") || arg == &String::from("--backup") {
                 self.backup = true;
+            } else if arg == &String::from("-s") || arg == &String::from("--save-on-exit") {
+                self.save_on_exit = true;
+            } else if arg == &String::from("-y") || arg == &String::from("--clipboard") {
+                self.clipboard = true;
             } else if arg == &String::from("-c") || arg == &String::from("--config") {
                 let next_arg = args.get(i + 1);
                 match &next_arg {
@@ -59,7 +69,7 @@ impl Cli {
                     None => return Err(error::Error::ConfigNotFound),
                 }
             } else if arg == &String::from("-h") || arg == &String::from("--help") {
-                println!("Usage:\n\tzed [options] [file(s)]\nOptions:\n\t-b\t\tStore backup of file\n\t-c\t\tSpecify custom config\n\t-h, --help\tShow this message");
+                println!("Usage:\n\tzed [options] [file(s)]\nOptions:\n\t-b\t\tStore backup of file\n\t-s\t\tSave modified files on exit\n\t-y\t\tCopy yanks to the clipboard (OSC 52)\n\t-c\t\tSpecify custom config\n\t-h, --help\tShow this message");
             } else {
                 if arg.starts_with("-") {
                     eprintln!("Invalid Option\nTry zed --help for more information");
@@ -0,0 +1,14 @@
+// Locations of zed's own files
+use std::env;
+use std::path::PathBuf;
+
+// $XDG_DATA_HOME/zed, or ~/.local/share/zed
+pub fn data_dir() -> PathBuf {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(x) if !x.is_empty() => PathBuf::from(x).join("zed"),
+        _ => {
+            let home = env::var_os("HOME").unwrap_or_else(|| ".".into());
+            PathBuf::from(home).join(".local/share/zed")
+        }
+    }
+}
@@ -8,4 +8,26 @@ pub enum Error {
     CouldNotRender,
     #[snafu(display("Position out of bounds"))]
     CouldNotMove,
+    #[snafu(display("Could not save file"))]
+    CouldNotSave,
+    #[snafu(display("There are unsaved changes"))]
+    UnsavedChanges,
+    #[snafu(display("Not an editor command: {}", cmd))]
+    UnknownCommand { cmd: String },
+    #[snafu(display("Invalid range"))]
+    InvalidRange,
+    #[snafu(display("Mark not set"))]
+    MarkNotSet,
+    #[snafu(display("No file name"))]
+    NoFileName,
+    #[snafu(display("Argument required"))]
+    MissingArgument,
+    #[snafu(display("Invalid pattern: {}", pattern))]
+    InvalidPattern { pattern: String },
+    #[snafu(display("Invalid flags: {}", flags))]
+    InvalidFlags { flags: String },
+    #[snafu(display("No previous search pattern"))]
+    NoPattern,
+    #[snafu(display("Pattern not found: {}", pattern))]
+    PatternNotFound { pattern: String },
 }
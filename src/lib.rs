@@ -5,7 +5,7 @@
 
 pub mod cli;
 
-//pub mod config; // Search, Copy + Paste, Matching, Syntax
+pub mod config;
 
 pub mod backend;
 
@@ -15,4 +15,4 @@ pub mod error;
 
 pub mod ui; // Dashboard, Tab Bar, Bufferline, Text
 
-//mod utils; // Generic Fuzzy finder, over: files, grep, colorscheme
+pub mod utils; // Generic Fuzzy finder, over: files, grep, colorscheme
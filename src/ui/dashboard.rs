@@ -2,7 +2,7 @@ use crate::cli::Target;
 use crate::error::Error;
 use zui_core::style::{self, Style};
 
-use super::finder::FileFinder;
+use super::finder::{FileSource, GrepSource, Picker};
 use super::Component;
 use std::convert::TryFrom;
 use std::io::Write;
@@ -23,6 +23,28 @@ impl Dashboard {
         self.dir = p;
         self
     }
+
+    // SPC f, the file picked, if any
+    fn find_file<T: Write>(&mut self, term: &mut Terminal<T>, keys: KeyIterator) -> Option<Target> {
+        term.clear_screen().unwrap();
+        let mut finder = Picker::new().set_source(FileSource::default().set_dir(self.dir.clone()));
+        if let Ok(Some(t)) = finder.render(term, keys) {
+            return Some(t);
+        }
+        self.view(term).unwrap();
+        None
+    }
+
+    // SPC g, the file and line picked, if any
+    fn live_grep<T: Write>(&mut self, term: &mut Terminal<T>, keys: KeyIterator) -> Option<Target> {
+        term.clear_screen().unwrap();
+        let mut grep = Picker::new().set_source(GrepSource::default().set_dir(self.dir.clone()));
+        if let Ok(Some(t)) = grep.render(term, keys) {
+            return Some(t);
+        }
+        self.view(term).unwrap();
+        None
+    }
 }
 
 impl Component for Dashboard {
@@ -134,14 +156,14 @@ impl Component for Dashboard {
                 }
                 Key::Enter | Key::Tab => match self.selected_option {
                     1 => {
-                        // Render FileFinder
-                        term.clear_screen().unwrap();
-                        let mut finder = FileFinder::new().set_dir(self.dir.clone());
-                        let f = finder.render(term, keys.clone());
-                        if let Ok(Target::File(x)) = f {
-                            return Ok(Target::File(x));
+                        if let Some(t) = self.find_file(term, keys.clone()) {
+                            return Ok(t);
+                        }
+                    }
+                    3 => {
+                        if let Some(t) = self.live_grep(term, keys.clone()) {
+                            return Ok(t);
                         }
-                        self.view(term).unwrap();
                     }
                     _ => continue,
                 },
@@ -176,14 +198,9 @@ impl Component for Dashboard {
                     Some(x) => match x {
                         // Render the File Finder
                         Key::Char('f') => {
-                            // Render FileFinder
-                            term.clear_screen().unwrap();
-                            let mut finder = FileFinder::new().set_dir(self.dir.clone());
-                            let f = finder.render(term, keys.clone());
-                            if let Ok(Target::File(x)) = f {
-                                return Ok(Target::File(x));
+                            if let Some(t) = self.find_file(term, keys.clone()) {
+                                return Ok(t);
                             }
-                            self.view(term).unwrap();
                         }
                         // Render the color switcher
                         Key::Char('c') => {
@@ -191,7 +208,9 @@ impl Component for Dashboard {
                         }
                         // Live Grepper
                         Key::Char('g') => {
-                            continue;
+                            if let Some(t) = self.live_grep(term, keys.clone()) {
+                                return Ok(t);
+                            }
                         }
                         _ => continue,
                     },
@@ -213,6 +232,7 @@ impl Component for Dashboard {
         match self.handle_key(term, keys) {
             Ok(s) => match s {
                 Target::File(x) => return Ok(Target::File(x)),
+                Target::Line(x, line) => return Ok(Target::Line(x, line)),
                 _ => return Ok(Target::Empty),
             },
             Err(s) => Err(s),
@@ -1,12 +1,30 @@
+use super::finder::{BufferSource, CommandSource, FileSource, Picker};
+use super::keys;
+use super::replace::{self, ReplacePanel};
+use super::tabbar::{self, TabAction, TabBar};
+use super::window::{self, Direction, Layout, Rect, SplitDir, Window};
 use super::Component;
 use crate::backend::buffer;
 use crate::backend::buffer::Buffer;
+use crate::backend::command::Command;
 use crate::backend::editor;
+use crate::backend::motion::{self, Find, Motion};
+use crate::backend::operator::Operator;
+use crate::backend::register::{self, Register, Registers};
+use crate::backend::search::{self, Substitute};
+use crate::backend::selection::{Kind, Selection};
+use crate::backend::textobject::TextObject;
+use crate::cli::Target;
 use crate::error::Error;
 use buffer::Mode;
+use regex::Regex;
 use ropey::RopeSlice;
+use std::collections::HashMap;
 use std::io;
 use std::io::Write;
+use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
 use zui_core::color::{self, fg, Color};
 use zui_core::key::{Key, KeyIterator};
 use zui_core::term::Terminal;
@@ -23,6 +41,10 @@ fn draw_statusline<T: Write>(
 
     // Vi-mode type
     let mut subtract_length = buf.lang_str.chars().count() as u16 + 1;
+    if buf.is_modified() {
+        subtract_length += 4;
+    }
+    let before_mode = subtract_length;
     term.hide_cursor().unwrap();
 
     match &buf.mode {
@@ -47,10 +69,17 @@ fn draw_statusline<T: Write>(
 
             subtract_length += 7;
         }
+        Mode::Command => {
+            term.print(color::bg(Color::RGB(153, 102, 0))).unwrap();
+            term.print(" COMMAND ").unwrap();
+            term.print(color::bg(Color::Reset)).unwrap();
+
+            subtract_length += 8;
+        }
     }
 
     // File-name
-    if let Some(file_path) = buf.p {
+    if let Some(file_path) = &buf.p {
         let file_str = format!(" {:?}", file_path);
 
         let mut colored_string = String::new();
@@ -58,6 +87,9 @@ fn draw_statusline<T: Write>(
         colored_string.push_str(format!("{}", color::bg(Color::RGB(61, 61, 41))).as_str());
         term.print(colored_string).unwrap();
         term.print(file_str).unwrap();
+        if buf.is_modified() {
+            term.print(" [+]").unwrap();
+        }
         term.print(format!("{}", color::bg(Color::Reset)).as_str())
             .unwrap();
 
@@ -65,7 +97,8 @@ fn draw_statusline<T: Write>(
         let mut print_string = String::new();
 
         print_string.push_str(format!("{}", color::bg(Color::RGB(61, 61, 41))).as_str());
-        for _ in 0..x_size - subtract_length - file_path.as_os_str().len() as u16 - 4 {
+        let used = subtract_length + file_path.as_os_str().len() as u16 + 4;
+        for _ in 0..x_size.saturating_sub(used) {
             print_string.push(' ');
         }
 
@@ -77,6 +110,11 @@ fn draw_statusline<T: Write>(
         term.print(" ").unwrap();
         term.print(format!("{}", color::bg(Color::Reset)).as_str())
             .unwrap();
+    } else {
+        // Over whatever was there, the screen isn't cleared
+        let mode_width = subtract_length - before_mode + 1;
+        term.print(" ".repeat(x_size.saturating_sub(mode_width) as usize))
+            .unwrap();
     }
 
     term.show_cursor().unwrap();
@@ -84,220 +122,3205 @@ fn draw_statusline<T: Write>(
     Ok(())
 }
 
-pub struct Editor<'a> {
-    pub editor: Option<editor::Editor<'a>>,
+const TAB_WIDTH: usize = 4;
+// First row of text, the tab bar is above it
+const TEXT_TOP: u16 = 2;
+// How deep macros can call each other
+const MAX_MACRO_DEPTH: usize = 100;
+// Commands offered by SPC :
+const COMMANDS: &[(&str, &str)] = &[
+    ("write", "Save the buffer"),
+    ("wall", "Save every buffer"),
+    ("xit", "Save and close the window"),
+    ("xall", "Save every buffer and quit"),
+    ("quit", "Close the window"),
+    ("qall", "Quit"),
+    ("edit", "Open a file"),
+    ("split", "Split the window"),
+    ("vsplit", "Split the window side by side"),
+    ("close", "Close the window"),
+    ("only", "Close every other window"),
+    ("buffer", "Go to a buffer by its number"),
+    ("bnext", "Next buffer"),
+    ("bprevious", "Previous buffer"),
+    ("bdelete", "Close the buffer"),
+    ("buffers", "List the buffers"),
+    ("delete", "Delete the line"),
+    ("substitute", "Replace in the line"),
+];
+
+pub struct Editor {
+    pub editor: Option<editor::Editor>,
     pub current_line: usize,
     pub current_index: usize,
-    shown_lines: (usize, usize), // Exclusive, inclusive
+    shown_lines: (usize, usize), // First shown line, one past the last shown line
+    // First screen column shown, long lines scroll sideways
+    shown_col: usize,
+    // Screen column kept when moving up and down, usize::MAX sticks to the end
+    desired_col: usize,
+    tabbar: TabBar,
+    // The active window's cursor lives in the fields above,
+    // `windows` holds it for every other window
+    windows: Vec<Window>,
+    layout: Layout,
+    active_win: usize,
+    next_win: usize,
+    // Text typed after `prompt`, which is `:`, `/` or `?`
+    cmdline: String,
+    prompt: char,
+    // Cursor and scroll to go back to when a search is cancelled
+    search_start: (usize, usize, usize),
+    // Pattern and direction of the last search, for n and N
+    search: Option<(String, bool)>,
+    // `:s///c` waiting for y/n/a/q
+    confirm: Option<Confirm>,
+    // Shown on the bottom row until the next key, true for errors
+    message: Option<(String, bool)>,
+    pending: Pending,
+    // Anchor of the visual selection, the cursor is the other end
+    visual: Option<Selection>,
+    block_insert: Option<BlockInsert>,
+    // Register and keys of the macro being recorded
+    recording: Option<(char, Vec<Key>)>,
+    // For @@
+    last_macro: Option<char>,
+    // Macros running inside each other
+    macro_depth: usize,
+    // For `.`
+    last_change: Option<Repeat>,
+    // A change still in insert mode, it becomes `last_change` on Esc
+    inserting: Option<Repeat>,
+    // A y/n question waiting for its answer
+    asking: Option<Ask>,
+    // For ; and ,
+    last_find: Option<Find>,
+    // Text rows on the screen by where they start, and the screen size
+    // then. Views only draw the rows that changed.
+    drawn: HashMap<(u16, u16), String>,
+    drawn_size: (u16, u16),
+}
+
+// Questions about throwing away unsaved work, answered by the next key
+#[derive(Debug, Clone, Copy)]
+enum Ask {
+    Quit,
+    // Close the buffer with this id
+    Close(usize),
+}
+
+// Text typed at the start of a block goes on all its lines
+#[derive(Debug, Clone, Copy)]
+struct BlockInsert {
+    first: usize,
+    last: usize,
+    // Screen column, usize::MAX for the line end
+    col: usize,
+    // Where typing started on the first line
+    start: usize,
+}
+
+// Matches of a `:s` being replaced one by one
+#[derive(Debug, Clone)]
+struct Confirm {
+    // Char ranges in the buffer before any replacement, and their new text
+    matches: Vec<(Range<usize>, String)>,
+    next: usize,
+    // Chars added by the replacements so far, to find the later matches
+    shift: isize,
+    done: usize,
+    // Start of the last replacement
+    cursor: usize,
+}
+
+impl Confirm {
+    // Where the next match is now, and its replacement
+    fn current(&self) -> Option<(Range<usize>, &str)> {
+        let (range, text) = self.matches.get(self.next)?;
+        let start = (range.start as isize + self.shift) as usize;
+        let end = (range.end as isize + self.shift) as usize;
+        Some((start..end, text))
+    }
+}
+
+// A change that `.` can make again
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Operator {
+        op: Operator,
+        target: Span,
+        count: usize,
+        register: Option<char>,
+    },
+    Paste {
+        register: Option<char>,
+        count: usize,
+        before: bool,
+    },
+    // i, a, I, A, o and O, the typed text goes in `count` times
+    Insert {
+        key: Key,
+        count: usize,
+    },
+}
+
+// What an operator acted on
+#[derive(Debug, Clone, Copy)]
+enum Span {
+    Motion(Motion),
+    // The object and whether it was `i`
+    Object(TextObject, bool),
+    // `count` lines, like dd or a V selection
+    Lines,
+    // `count` chars, like a v selection
+    Chars,
+}
+
+// The last change and the keys typed in the insert session after it
+#[derive(Debug, Clone)]
+struct Repeat {
+    action: Action,
+    typed: Vec<Key>,
+}
+
+// Keys typed so far of a normal mode command like `3d2w`
+#[derive(Debug, Default)]
+struct Pending {
+    count: Option<usize>,
+    op: Option<Operator>,
+    // Count typed after the operator, multiplies the first one
+    op_count: Option<usize>,
+    // After `g`, for gg, gU, gu and g~
+    g: bool,
+    // After the `i` or `a` of a text object, true for `i`
+    object: Option<bool>,
+    // Picked with `"x`
+    register: Option<char>,
+    // After `"`, or Ctrl-r in insert mode
+    quote: bool,
+    // After f, F, t and T, which way and whether it was t or T
+    find: Option<(bool, bool)>,
+    // After `q` and `@`
+    record: bool,
+    at: bool,
+    // After SPC, Ctrl-w and SPC t
+    space: bool,
+    window: bool,
+    tab: bool,
+}
+
+impl Pending {
+    fn count(&self) -> Option<usize> {
+        match (self.count, self.op_count) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1))),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count.is_none()
+            && self.op.is_none()
+            && self.op_count.is_none()
+            && !self.g
+            && self.object.is_none()
+            && self.register.is_none()
+            && !self.quote
+            && self.find.is_none()
+            && !self.record
+            && !self.at
+            && !self.space
+            && !self.window
+            && !self.tab
+    }
 }
 
 type MoveResult = Result<(), Error>;
-impl<'a> Editor<'a> {
-    pub fn set_editor(mut self, e: editor::Editor<'a>) -> Editor<'a> {
+impl Editor {
+    pub fn set_editor(mut self, e: editor::Editor) -> Editor {
         self.editor = Some(e);
         self
     }
 
-    pub fn push_buf(&mut self, buf: &'a Buffer<'a>) {
+    pub fn push_buf(&mut self, buf: Buffer) {
         if let Some(e) = &mut self.editor {
-            e.buffers.push(buf);
-            e.cur_buf = Some(e.buffers[e.buffers.len() - 1]);
+            e.add(buf);
         }
     }
 
-    // Movement methods
-    pub fn move_up<T: Write>(&mut self, term: &mut Terminal<T>) -> MoveResult {
-        if self.current_line > 1 {
-            self.current_line -= 1;
-            if self.current_line > self.shown_lines.0 {
-                term.set_cursor_to(term.x_pos, term.y_pos - 1).unwrap();
-            } else {
-                self.shown_lines.0 -= 1;
-                self.view(term).unwrap();
+    // Start on `line`, 1-based
+    pub fn goto_line(&mut self, line: usize) {
+        let lines = self.cur_buf().map_or(1, |b| b.line_count).max(1);
+        self.current_line = line.clamp(1, lines);
+        self.current_index = 1;
+    }
+
+    fn cur_buf(&self) -> Option<&Buffer> {
+        self.editor.as_ref().and_then(|e| e.cur_buf())
+    }
+
+    fn cur_buf_mut(&mut self) -> Option<&mut Buffer> {
+        self.editor.as_mut().and_then(|e| e.cur_buf_mut())
+    }
+
+    // Char index of the cursor in the rope
+    fn cursor_char(&self) -> usize {
+        match self.cur_buf() {
+            Some(buf) => buf.rope.line_to_char(self.current_line - 1) + self.current_index - 1,
+            None => 0,
+        }
+    }
+
+    // Put the cursor on the char at `char_idx`
+    fn set_cursor_char(&mut self, char_idx: usize) {
+        let pos = self.cur_buf().map(|buf| {
+            let line = buf.rope.char_to_line(char_idx);
+            (line, char_idx - buf.rope.line_to_char(line))
+        });
+        if let Some((line, col)) = pos {
+            self.current_line = line + 1;
+            self.current_index = col + 1;
+        }
+    }
+
+    // Keep the cursor inside the buffer and the current line
+    // A window's saved line can be past the end when the buffer
+    // shrank in another window
+    fn clamp_cursor(&mut self) {
+        let lines = match self.cur_buf() {
+            Some(buf) => buf.line_count.max(1),
+            None => return,
+        };
+        self.current_line = self.current_line.clamp(1, lines);
+        if let Some(buf) = self.cur_buf() {
+            let mut max = buf.line_len(self.current_line - 1);
+            if let Mode::Normal | Mode::Visual = buf.mode {
+                max = max.saturating_sub(1);
+            }
+            if self.current_index > max + 1 {
+                self.current_index = max + 1;
             }
-            return Ok(());
         }
-        Err(Error::CouldNotMove)
     }
 
-    pub fn move_down<T: Write>(&mut self, term: &mut Terminal<T>, buf: &Buffer<'a>) -> MoveResult {
-        if buf.line_count > self.current_line {
-            self.current_line += 1;
-            if self.current_line <= (self.shown_lines.1) {
-                term.set_cursor_to(term.x_pos, term.y_pos + 1).unwrap();
-            } else {
-                self.shown_lines.0 += 1;
-                self.view(term).unwrap();
+    // Screen space for windows, between the tab bar and the command line
+    fn window_area<T: Write>(&self, term: &Terminal<T>) -> Rect {
+        let (x, y) = term.get_size();
+        Rect {
+            x: 1,
+            y: TEXT_TOP,
+            width: x,
+            height: y.saturating_sub(TEXT_TOP).max(2),
+        }
+    }
+
+    fn active_rect<T: Write>(&self, term: &Terminal<T>) -> Rect {
+        let area = self.window_area(term);
+        match self
+            .layout
+            .rects(area)
+            .into_iter()
+            .find(|(id, _)| *id == self.active_win)
+        {
+            Some((_, r)) => r,
+            None => area,
+        }
+    }
+
+    // Rows available for text in the active window, above its statusline
+    fn text_height<T: Write>(&self, term: &Terminal<T>) -> usize {
+        (self.active_rect(term).height as usize)
+            .saturating_sub(1)
+            .max(1)
+    }
+
+    // Store the cursor of the active window
+    fn save_window(&mut self) {
+        let buf = match self.editor.as_ref().and_then(|e| e.cur_buf) {
+            Some(b) => b,
+            None => return,
+        };
+        let win = Window {
+            id: self.active_win,
+            buf,
+            current_line: self.current_line,
+            current_index: self.current_index,
+            shown_lines: self.shown_lines,
+            shown_col: self.shown_col,
+        };
+        match self.windows.iter_mut().find(|w| w.id == win.id) {
+            Some(w) => *w = win,
+            None => self.windows.push(win),
+        }
+    }
+
+    // Make window `id` active
+    fn load_window(&mut self, id: usize) {
+        let win = match self.windows.iter().find(|w| w.id == id) {
+            Some(w) => w.clone(),
+            None => return,
+        };
+        self.active_win = id;
+        let shown = match &mut self.editor {
+            Some(e) => e.switch_to(win.buf),
+            None => false,
+        };
+        if shown {
+            self.current_line = win.current_line;
+            self.current_index = win.current_index;
+            self.shown_lines = win.shown_lines;
+            self.shown_col = win.shown_col;
+        }
+        self.clamp_cursor();
+        self.remember_col();
+    }
+
+    // Ctrl-w s and Ctrl-w v
+    fn split_window(&mut self, dir: SplitDir) {
+        self.save_window();
+        let id = self.next_win;
+        self.next_win += 1;
+
+        if self.layout.split(self.active_win, id, dir) {
+            if let Some(w) = self.windows.iter().find(|w| w.id == self.active_win) {
+                let win = Window { id, ..w.clone() };
+                self.windows.push(win);
             }
-            return Ok(());
+            self.load_window(id);
+        }
+    }
+
+    // Ctrl-w c
+    fn close_window(&mut self) {
+        if self.layout.windows().len() < 2 {
+            return;
+        }
+        let id = self.active_win;
+        self.layout.remove(id);
+        self.windows.retain(|w| w.id != id);
+        if let Some(next) = self.layout.windows().first() {
+            self.load_window(*next);
         }
-        Err(Error::CouldNotMove)
     }
 
-    //    pub fn move_right<T: Write>(&mut self, term: &mut Terminal<T>, buf: &Buffer<'a>) -> MoveResult {
-    //
-    //    }
+    // Ctrl-w o
+    fn only_window(&mut self) {
+        self.save_window();
+        self.layout = Layout::Window(self.active_win);
+        let id = self.active_win;
+        self.windows.retain(|w| w.id == id);
+    }
 
-    fn move_to_line<T: Write>(&mut self, term: &mut Terminal<T>) -> MoveResult {
-        Ok(())
+    fn focus_window<T: Write>(&mut self, term: &Terminal<T>, dir: Direction) {
+        let rects = self.layout.rects(self.window_area(term));
+        if let Some(id) = window::neighbour(&rects, self.active_win, dir) {
+            self.save_window();
+            self.load_window(id);
+        }
     }
 
-    fn move_to_char<T: Write>(&mut self, term: &mut Terminal<T>) -> MoveResult {
-        Ok(())
+    // Ctrl-w w
+    fn cycle_window(&mut self) {
+        let ids = self.layout.windows();
+        if let Some(i) = ids.iter().position(|id| *id == self.active_win) {
+            self.save_window();
+            self.load_window(ids[(i + 1) % ids.len()]);
+        }
     }
-}
 
-fn draw_line<T: Write>(
-    term: &mut Terminal<T>,
-    line: &RopeSlice,
-    x_size: u16,
-) -> Result<(), io::ErrorKind> {
-    term.print(line).unwrap();
-    term.set_cursor_to(term.x_pos, term.y_pos + 1).unwrap();
-    Ok(())
-}
+    // The key after Ctrl-w
+    fn window_command<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> super::ZedError {
+        match key {
+            Key::Char('s') | Key::Char('S') => self.split_window(SplitDir::Horizontal),
+            Key::Char('v') => self.split_window(SplitDir::Vertical),
+            Key::Char('c') | Key::Char('q') => self.close_window(),
+            Key::Char('o') => self.only_window(),
+            Key::Char('w') | Key::Ctrl('w') => self.cycle_window(),
+            Key::Char('h') | Key::Left => self.focus_window(term, Direction::Left),
+            Key::Char('j') | Key::Down => self.focus_window(term, Direction::Down),
+            Key::Char('k') | Key::Up => self.focus_window(term, Direction::Up),
+            Key::Char('l') | Key::Right => self.focus_window(term, Direction::Right),
+            Key::Char('+') => {
+                self.layout.resize(self.active_win, SplitDir::Horizontal, 5);
+            }
+            Key::Char('-') => {
+                self.layout
+                    .resize(self.active_win, SplitDir::Horizontal, -5);
+            }
+            Key::Char('>') => {
+                self.layout.resize(self.active_win, SplitDir::Vertical, 5);
+            }
+            Key::Char('<') => {
+                self.layout.resize(self.active_win, SplitDir::Vertical, -5);
+            }
+            Key::Char('=') => self.layout.equalize(),
+            _ => return Ok(()),
+        }
+        self.scroll(term);
+        self.view(term)
+    }
 
-impl<'a> Component for Editor<'a> {
-    type Widget = Editor<'a>;
+    // Scroll so the cursor is visible. Returns true when the view moved.
+    fn scroll<T: Write>(&mut self, term: &Terminal<T>) -> bool {
+        let height = self.text_height(term);
+        let first = self.shown_lines.0;
+        if self.current_line <= self.shown_lines.0 {
+            self.shown_lines.0 = self.current_line - 1;
+        } else if self.current_line > self.shown_lines.0 + height {
+            self.shown_lines.0 = self.current_line - height;
+        }
 
-    // WidgetReturn
-    type WidgetReturn = ();
+        // Sideways, when the cursor is past the edge of a long line
+        let first_col = self.shown_col;
+        let cols = self.cur_buf().map(|buf| {
+            let line = buf.rope.line(self.current_line - 1);
+            let width = (self.active_rect(term).width as usize)
+                .saturating_sub(number_width(buf.line_count) as usize + 1)
+                .max(1);
+            (display_col(&line, self.current_index - 1), width)
+        });
+        if let Some((col, width)) = cols {
+            // Show as much as fits left of the cursor
+            if col < self.shown_col {
+                self.shown_col = col.saturating_sub(width - 1);
+            } else if col >= self.shown_col + width {
+                self.shown_col = col + 1 - width;
+            }
+        }
+        first != self.shown_lines.0 || first_col != self.shown_col
+    }
 
-    fn new() -> Self::Widget {
-        Editor {
-            editor: None,
-            current_line: 1,
-            current_index: 1,
-            shown_lines: (0, 1),
+    fn place_cursor<T: Write>(&self, term: &mut Terminal<T>) {
+        if let Some(Mode::Command) = self.cur_buf().map(|b| b.mode) {
+            let x = self.cmdline.chars().count() as u16 + 2;
+            term.set_cursor_to(x, term.rel_size.1).unwrap();
+            return;
+        }
+        let rect = self.active_rect(term);
+        if let Some(buf) = self.cur_buf() {
+            let line = buf.rope.line(self.current_line - 1);
+            let x = rect.x as usize
+                + number_width(buf.line_count) as usize
+                + 1
+                + display_col(&line, self.current_index - 1).saturating_sub(self.shown_col);
+            let y = rect.y as usize + self.current_line - self.shown_lines.0 - 1;
+            term.set_cursor_to(x as u16, y as u16).unwrap();
         }
     }
 
-    fn destroy<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
-        Ok(())
+    // Redraw only when scrolling, otherwise just move the cursor
+    fn refresh<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        self.clamp_cursor();
+        // The selection follows the cursor
+        if self.scroll(term) || self.visual.is_some() {
+            self.view(term)
+        } else {
+            self.place_cursor(term);
+            Ok(())
+        }
     }
 
-    fn view<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
-        // Inital values
-        let (x, y) = term.get_size(); // TODO: Fix this
-        term.clear_screen().unwrap();
+    // Keep the current screen column for moving up and down
+    fn remember_col(&mut self) {
+        if let Some(buf) = self.cur_buf() {
+            let line = buf.rope.line(self.current_line - 1);
+            self.desired_col = display_col(&line, self.current_index - 1);
+        }
+    }
+
+    // Move to the remembered column on the current line
+    fn apply_desired_col(&mut self) {
+        let pos = self.cur_buf().map(|buf| {
+            let line = self.current_line - 1;
+            motion::char_at_col(buf, line, self.desired_col, TAB_WIDTH)
+                - motion::line_start(buf, line)
+        });
+        if let Some(col) = pos {
+            self.current_index = col + 1;
+        }
+    }
 
-        let (_curr_x, curr_y) = (term.x_pos, term.y_pos);
+    // Put the cursor at `char_idx` after a motion
+    fn motion_to<T: Write>(&mut self, term: &mut Terminal<T>, char_idx: usize) -> MoveResult {
+        let len = self.cur_buf().map_or(0, |b| b.rope.len_chars());
+        self.set_cursor_char(char_idx.min(len));
+        self.clamp_cursor();
+        self.remember_col();
+        self.refresh(term)
+    }
 
-        // Render Lines
-        if let Some(e) = &mut self.editor {
-            if let Some(cur_buf) = e.cur_buf {
-                // Calculate last line
-                let mut last_line = self.shown_lines.0 + y as usize;
-                if last_line >= cur_buf.line_count {
-                    last_line = cur_buf.line_count;
-                }
-                self.shown_lines.1 = last_line;
-                // Currently selected buffer
-                term.set_cursor_to(1, term.rel_size.1).unwrap();
-                draw_statusline(term, cur_buf, x).unwrap();
-
-                term.set_cursor_to(1, 1).unwrap();
-                let buff_lc = cur_buf.line_count;
-
-                let mut buf_offset = 0;
-                // Replace with logarithm
-                match buff_lc {
-                    0..=9 => {
-                        buf_offset = 1;
-                    }
-                    10..=99 => {
-                        buf_offset = 2;
+    // Counts, operators and motions in normal mode, one key at a time
+    // Returns false when `key` is not part of that grammar
+    fn normal_key<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> Result<bool, Error> {
+        if self.pending.quote {
+            self.pending.quote = false;
+            match key {
+                Key::Char(c) if Registers::is_valid(c) => self.pending.register = Some(c),
+                _ => self.pending = Pending::default(),
+            }
+            return Ok(true);
+        }
+
+        // The char to find, which can be a digit
+        if let Some((forward, till)) = self.pending.find.take() {
+            let c = match key {
+                Key::Char(c) => c,
+                _ => {
+                    self.pending = Pending::default();
+                    return Ok(true);
+                }
+            };
+            let f = Find { c, forward, till };
+            self.last_find = Some(f);
+            return self.motion_key(term, Motion::Find(f));
+        }
+
+        // A leading 0 is a motion, not a count
+        if let Key::Char(c) = key {
+            if let Some(d) = c.to_digit(10) {
+                let typed = if self.pending.op.is_some() {
+                    &mut self.pending.op_count
+                } else {
+                    &mut self.pending.count
+                };
+                if !self.pending.g && (d != 0 || typed.is_some()) {
+                    *typed = Some(
+                        typed
+                            .unwrap_or(0)
+                            .saturating_mul(10)
+                            .saturating_add(d as usize),
+                    );
+                    return Ok(true);
+                }
+            }
+        }
+
+        let count = self.pending.count();
+        if let Some(inner) = self.pending.object {
+            let pending = mem::take(&mut self.pending);
+            if let (Key::Char(c), Some(op)) = (key, pending.op) {
+                if let Some(obj) = TextObject::from_char(c) {
+                    let count = count.unwrap_or(1);
+                    let target = Span::Object(obj, inner);
+                    let _ = self.operator_target(term, op, target, count, pending.register);
+                }
+            }
+            return Ok(true);
+        }
+
+        let motion = if self.pending.g {
+            self.pending.g = false;
+            match key {
+                Key::Char('g') => Motion::GotoLine(count.unwrap_or(1).saturating_sub(1)),
+                Key::Char('U') => return self.operator(term, Operator::Upper),
+                Key::Char('u') => return self.operator(term, Operator::Lower),
+                Key::Char('~') => return self.operator(term, Operator::ToggleCase),
+                _ => {
+                    self.pending = Pending::default();
+                    return Ok(true);
+                }
+            }
+        } else {
+            let m = match key {
+                Key::Left => Some(Motion::Left),
+                Key::Right => Some(Motion::Right),
+                Key::Up => Some(Motion::Up),
+                Key::Down => Some(Motion::Down),
+                Key::Char('g') => {
+                    self.pending.g = true;
+                    return Ok(true);
+                }
+                Key::Char('"') if self.pending.op.is_none() => {
+                    self.pending.quote = true;
+                    return Ok(true);
+                }
+                Key::Char('q') if self.pending.op.is_none() => {
+                    if self.recording.is_some() {
+                        self.stop_recording(term);
+                    } else {
+                        self.pending.record = true;
                     }
-                    100..=999 => {
-                        buf_offset = 3;
+                    return Ok(true);
+                }
+                Key::Char('@') if self.pending.op.is_none() => {
+                    self.pending.at = true;
+                    return Ok(true);
+                }
+                Key::Char(c @ 'p') | Key::Char(c @ 'P') if self.pending.op.is_none() => {
+                    let pending = mem::take(&mut self.pending);
+                    let count = pending.count().unwrap_or(1);
+                    let before = c == 'P';
+                    if self.paste(term, pending.register, count, before).is_ok() {
+                        self.record_change(Action::Paste {
+                            register: pending.register,
+                            count,
+                            before,
+                        });
                     }
-                    1000..=9999 => {
-                        buf_offset = 4;
+                    return Ok(true);
+                }
+                Key::Char(c @ 'n')
+                | Key::Char(c @ 'N')
+                | Key::Char(c @ '*')
+                | Key::Char(c @ '#')
+                    if self.pending.op.is_none() =>
+                {
+                    let count = mem::take(&mut self.pending).count().unwrap_or(1);
+                    let found = match c {
+                        'n' | 'N' => self.search_next(term, c == 'N', count),
+                        _ => self.search_word(term, c == '*', count),
+                    };
+                    if let Err(err) = found {
+                        self.message = Some((format!("{}", err), true));
+                        self.draw_cmdline(term);
+                        self.place_cursor(term);
                     }
-                    _ => {
-                        buf_offset = 5;
+                    return Ok(true);
+                }
+                Key::Char('i')
+                | Key::Char('a')
+                | Key::Char('I')
+                | Key::Char('A')
+                | Key::Char('o')
+                | Key::Char('O')
+                    if self.pending.op.is_none() =>
+                {
+                    let count = mem::take(&mut self.pending).count().unwrap_or(1);
+                    self.enter_insert(term, key)?;
+                    self.record_change(Action::Insert { key, count });
+                    return Ok(true);
+                }
+                Key::Char('.') if self.pending.op.is_none() => {
+                    let count = mem::take(&mut self.pending).count();
+                    let _ = self.repeat_change(term, count);
+                    return Ok(true);
+                }
+                Key::Char(c @ 'f')
+                | Key::Char(c @ 'F')
+                | Key::Char(c @ 't')
+                | Key::Char(c @ 'T') => {
+                    self.pending.find = Some((c == 'f' || c == 't', c == 't' || c == 'T'));
+                    return Ok(true);
+                }
+                Key::Char(c @ ';') | Key::Char(c @ ',') => match self.last_find {
+                    Some(f) if c == ';' => Some(Motion::FindAgain(f)),
+                    Some(f) => Some(Motion::FindAgain(f.reversed())),
+                    None => {
+                        self.pending = Pending::default();
+                        return Ok(true);
                     }
+                },
+                Key::Char('G') => Some(match count {
+                    Some(n) => Motion::GotoLine(n.saturating_sub(1)),
+                    None => Motion::LastLine,
+                }),
+                // gUU and guu
+                Key::Char('U') if self.pending.op == Some(Operator::Upper) => {
+                    return self.operator(term, Operator::Upper)
+                }
+                Key::Char('u') if self.pending.op == Some(Operator::Lower) => {
+                    return self.operator(term, Operator::Lower)
+                }
+                Key::Char('~') if self.pending.op == Some(Operator::ToggleCase) => {
+                    return self.operator(term, Operator::ToggleCase)
+                }
+                // Text objects
+                Key::Char(c @ 'i') | Key::Char(c @ 'a') if self.pending.op.is_some() => {
+                    self.pending.object = Some(c == 'i');
+                    return Ok(true);
                 }
+                // Y is yy
+                Key::Char('Y') if self.pending.op.is_none() => {
+                    self.pending.op = Some(Operator::Yank);
+                    return self.operator(term, Operator::Yank);
+                }
+                // Shorthands for an operator and a motion
+                Key::Char(c @ 'x')
+                | Key::Char(c @ 'X')
+                | Key::Char(c @ 'D')
+                | Key::Char(c @ 'C')
+                | Key::Char(c @ 's')
+                    if self.pending.op.is_none() =>
+                {
+                    let (op, m) = match c {
+                        'x' => (Operator::Delete, Motion::Right),
+                        'X' => (Operator::Delete, Motion::Left),
+                        'D' => (Operator::Delete, Motion::LineEnd),
+                        'C' => (Operator::Change, Motion::LineEnd),
+                        _ => (Operator::Change, Motion::Right),
+                    };
+                    self.pending.op = Some(op);
+                    Some(m)
+                }
+                Key::Char(c) => match Operator::from_char(c) {
+                    Some(op) => return self.operator(term, op),
+                    None => Motion::from_char(c),
+                },
+                _ => None,
+            };
+            match m {
+                Some(m) => m,
+                None => return Ok(false),
+            }
+        };
 
-                for line in self.shown_lines.0..(self.shown_lines.1 - 1) {
-                    if buff_lc > line {
-                        // Line numbers + offset
-                        term.print(fg(Color::RGB(153, 153, 102))).unwrap();
-
-                        let mut o_str = String::new();
-                        let mut o_count = buf_offset;
-                        if (0..9).contains(&line) {
-                            o_count -= 1;
-                        } else if (9..99).contains(&line) {
-                            o_count -= 2;
-                        } else if (99..999).contains(&line) {
-                            o_count -= 3;
-                        } else if (999..9999).contains(&line) {
-                            o_count -= 4;
-                        }
-
-                        for _ in 0..o_count {
-                            o_str.push(' ');
-                        }
-                        term.print(o_str).unwrap();
-                        term.print(line + 1).unwrap();
-                        term.print(fg(Color::Reset)).unwrap();
-                        term.print(" ").unwrap();
-
-                        // Render text
-                        let curr_line = cur_buf.rope.line(line);
-                        draw_line(term, &curr_line, x).unwrap();
-                    } else {
-                        term.print("~").unwrap();
-                        term.set_cursor_to(term.x_pos, term.y_pos + 1).unwrap();
+        self.motion_key(term, motion)
+    }
+
+    // Move, or run the pending operator over `motion`
+    fn motion_key<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        motion: Motion,
+    ) -> Result<bool, Error> {
+        let pending = mem::take(&mut self.pending);
+        let count = pending.count().unwrap_or(1);
+        // Running into the edge of the buffer is fine
+        let _ = match pending.op {
+            Some(op) => {
+                let target = Span::Motion(motion);
+                self.operator_target(term, op, target, count, pending.register)
+            }
+            None => self.move_cursor(term, motion, count),
+        };
+        Ok(true)
+    }
+
+    // An operator key. Typing it twice (dd, yy, >>) acts on whole lines.
+    fn operator<T: Write>(&mut self, term: &mut Terminal<T>, op: Operator) -> Result<bool, Error> {
+        match self.pending.op {
+            None => self.pending.op = Some(op),
+            Some(p) if p == op => {
+                let pending = mem::take(&mut self.pending);
+                let count = pending.count().unwrap_or(1);
+                let target = Span::Lines;
+                self.operator_target(term, op, target, count, pending.register)?;
+            }
+            // `dc` means nothing
+            Some(_) => self.pending = Pending::default(),
+        }
+        Ok(true)
+    }
+
+    // Run `op` over `target` and remember it for `.`
+    fn operator_target<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        op: Operator,
+        target: Span,
+        count: usize,
+        reg: Option<char>,
+    ) -> MoveResult {
+        let idx = self.cursor_char();
+        let line = self.current_line - 1;
+        let buf = match self.cur_buf() {
+            Some(b) => b,
+            None => return Err(Error::CouldNotMove),
+        };
+
+        let (range, linewise) = match target {
+            Span::Motion(m) => {
+                // `dfx` without an x does nothing
+                if m.target(buf, idx, count).is_none() {
+                    return Err(Error::CouldNotMove);
+                }
+                let (mut range, linewise) = m.range(buf, idx, count);
+                // `cw` on a word leaves the space after it, like `ce`
+                let on_word = idx < buf.rope.len_chars() && !buf.rope.char(idx).is_whitespace();
+                if op == Operator::Change && matches!(m, Motion::WordForward(_)) && on_word {
+                    while range.end > range.start + 1
+                        && buf.rope.char(range.end - 1).is_whitespace()
+                    {
+                        range.end -= 1;
                     }
                 }
-                term.set_cursor_to(buf_offset + 2, curr_y).unwrap();
+                (range, linewise)
             }
-        }
-        term.show_cursor().unwrap();
+            Span::Object(obj, inner) => match obj.range(buf, idx, inner, count) {
+                Some(r) => r,
+                None => return Err(Error::CouldNotMove),
+            },
+            Span::Lines => {
+                let last = (line + count - 1).min(buf.line_count.saturating_sub(1));
+                (motion::line_range(buf, line, last), true)
+            }
+            Span::Chars => (idx..(idx + count).min(buf.rope.len_chars()), false),
+        };
 
+        self.run_operator(term, op, range, linewise, reg)?;
+        if op != Operator::Yank {
+            self.record_change(Action::Operator {
+                op,
+                target,
+                count,
+                register: reg,
+            });
+        }
         Ok(())
     }
 
-    fn handle_key<T: Write>(
+    // Apply `op` to `range` as one undo step, keeping the text in `reg`
+    fn run_operator<T: Write>(
         &mut self,
         term: &mut Terminal<T>,
-        keys: KeyIterator,
-    ) -> Result<Self::WidgetReturn, Error> {
-        for key in keys {
-            match key {
-                Key::Ctrl('q') => return Ok(()),
-                Key::Up | Key::Char('k') => {
-                    if let Err(_e) = self.move_up(term) {
-                        continue;
+        op: Operator,
+        range: Range<usize>,
+        linewise: bool,
+        reg: Option<char>,
+    ) -> MoveResult {
+        let cursor = self.cursor_char();
+        let (text, mut new_cursor) = match self.cur_buf_mut() {
+            Some(buf) => {
+                buf.begin_transaction(cursor);
+                let res = op.apply(buf, range, linewise);
+                // A change stays open until leaving insert mode
+                if op != Operator::Change {
+                    buf.commit_transaction(res.1);
+                }
+                res
+            }
+            None => return Err(Error::CouldNotMove),
+        };
+
+        if let Operator::Delete | Operator::Change | Operator::Yank = op {
+            let yank = op == Operator::Yank;
+            self.store_register(term, reg, Register::new(text, linewise), yank);
+        }
+        // yy doesn't move the cursor
+        if op == Operator::Yank && linewise {
+            new_cursor = cursor;
+        }
+
+        self.set_cursor_char(new_cursor);
+        if op == Operator::Change {
+            self.set_mode(Mode::Insert);
+            self.scroll(term);
+            return self.view(term);
+        }
+        self.clamp_cursor();
+        self.remember_col();
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // Keep a yank or delete, yanks also go to the clipboard when asked to
+    fn store_register<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        name: Option<char>,
+        reg: Register,
+        yank: bool,
+    ) {
+        let e = match &mut self.editor {
+            Some(e) => e,
+            None => return,
+        };
+        if matches!(name, Some('+') | Some('*')) || (yank && e.clipboard && name != Some('_')) {
+            term.print(register::osc52(&reg.text)).unwrap();
+        }
+        e.registers.store(name, reg, yank);
+    }
+
+    // p and P
+    fn paste<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        name: Option<char>,
+        count: usize,
+        before: bool,
+    ) -> MoveResult {
+        let reg = self
+            .editor
+            .as_ref()
+            .and_then(|e| e.registers.get(name.unwrap_or('"')))
+            .cloned();
+        let reg = match reg {
+            Some(r) if !r.text.is_empty() => r,
+            _ => return Err(Error::CouldNotMove),
+        };
+        let mut text = reg.text.repeat(count);
+
+        let cursor = self.cursor_char();
+        let line = self.current_line - 1;
+        let new_cursor = match self.cur_buf_mut() {
+            Some(buf) => {
+                buf.begin_transaction(cursor);
+                let new_cursor = if reg.blockwise {
+                    paste_block(buf, cursor, &reg.text, count, before)
+                } else if reg.linewise {
+                    // Whole lines go above or below the current one
+                    if !text.ends_with('\n') {
+                        text.push('\n');
                     }
+                    let at = if before {
+                        motion::line_start(buf, line)
+                    } else if line + 1 < buf.line_count {
+                        motion::line_start(buf, line + 1)
+                    } else {
+                        // After a last line without a line ending
+                        text.pop();
+                        text.insert(0, '\n');
+                        buf.rope.len_chars()
+                    };
+                    buf.insert_str(at, &text);
+                    let first = buf.rope.char_to_line(at) + usize::from(text.starts_with('\n'));
+                    motion::first_non_blank(buf, first)
+                } else {
+                    let at = if before || buf.line_len(line) == 0 {
+                        cursor
+                    } else {
+                        cursor + 1
+                    };
+                    buf.insert_str(at, &text);
+                    // On the last pasted char
+                    at + text.chars().count() - 1
+                };
+                buf.commit_transaction(new_cursor);
+                new_cursor
+            }
+            None => return Err(Error::CouldNotMove),
+        };
+
+        self.set_cursor_char(new_cursor);
+        self.clamp_cursor();
+        self.remember_col();
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // Move the cursor `count` times along `m`
+    fn move_cursor<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        m: Motion,
+        count: usize,
+    ) -> MoveResult {
+        let idx = self.cursor_char();
+        let (target, line_count) = match self.cur_buf() {
+            Some(buf) => (m.apply(buf, idx, count), buf.line_count),
+            None => return Err(Error::CouldNotMove),
+        };
+
+        // Up and down keep the column
+        if let Motion::Up | Motion::Down = m {
+            let line = if m == Motion::Up {
+                self.current_line.saturating_sub(count).max(1)
+            } else {
+                (self.current_line + count).min(line_count.max(1))
+            };
+            if line == self.current_line {
+                return Err(Error::CouldNotMove);
+            }
+            self.current_line = line;
+            self.apply_desired_col();
+            return self.refresh(term);
+        }
+
+        self.motion_to(term, target)?;
+        if m == Motion::LineEnd {
+            self.desired_col = usize::MAX;
+        }
+        Ok(())
+    }
+
+    // Selection of the active window, the cursor is its head
+    fn selection(&self) -> Option<Selection> {
+        let mut sel = self.visual?;
+        sel.head = self.cursor_char();
+        sel.to_end = self.desired_col == usize::MAX;
+        Some(sel)
+    }
+
+    // v, V and Ctrl-v
+    fn enter_visual<T: Write>(&mut self, term: &mut Terminal<T>, kind: Kind) -> super::ZedError {
+        self.visual = Some(Selection::new(kind, self.cursor_char()));
+        self.set_mode(Mode::Visual);
+        self.view(term)
+    }
+
+    // Stop selecting, the selected lines are kept in the '< and '> marks
+    fn end_visual(&mut self) -> Option<Selection> {
+        let sel = self.selection()?;
+        self.visual = None;
+        if let Some(buf) = self.cur_buf_mut() {
+            let (first, last) = sel.lines(buf);
+            buf.marks.insert('<', first + 1);
+            buf.marks.insert('>', last + 1);
+        }
+        self.set_mode(Mode::Normal);
+        Some(sel)
+    }
+
+    // Visual-mode keys
+    fn visual_key<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> super::ZedError {
+        // The register after `"` or the char after `f`
+        if self.pending.quote || self.pending.find.is_some() {
+            self.normal_key(term, key)?;
+            return Ok(());
+        }
+        // `viw` and friends select the object
+        if let Some(inner) = self.pending.object {
+            self.pending = Pending::default();
+            let idx = self.cursor_char();
+            let range = match (key, self.cur_buf()) {
+                (Key::Char(c), Some(buf)) => {
+                    TextObject::from_char(c).and_then(|obj| obj.range(buf, idx, inner, 1))
+                }
+                _ => None,
+            };
+            if let (Some((range, linewise)), Some(sel)) = (range, &mut self.visual) {
+                sel.anchor = range.start;
+                if linewise {
+                    sel.kind = Kind::Line;
+                }
+                self.set_cursor_char(range.end.saturating_sub(1).max(range.start));
+                self.remember_col();
+                self.scroll(term);
+            }
+            return self.view(term);
+        }
+
+        let op = match key {
+            Key::Char('d') | Key::Char('x') => Some(Operator::Delete),
+            Key::Char('y') => Some(Operator::Yank),
+            Key::Char('c') | Key::Char('s') => Some(Operator::Change),
+            Key::Char('>') => Some(Operator::Indent),
+            Key::Char('<') => Some(Operator::Dedent),
+            Key::Char('~') => Some(Operator::ToggleCase),
+            Key::Char('U') => Some(Operator::Upper),
+            Key::Char('u') => Some(Operator::Lower),
+            // Whole lines
+            Key::Char(c @ 'X') | Key::Char(c @ 'D') | Key::Char(c @ 'Y') | Key::Char(c @ 'C') => {
+                if let Some(sel) = &mut self.visual {
+                    sel.kind = Kind::Line;
+                }
+                match c {
+                    'Y' => Some(Operator::Yank),
+                    'C' => Some(Operator::Change),
+                    _ => Some(Operator::Delete),
                 }
-                Key::Down | Key::Char('j') => {
-                    if let Some(e) = &self.editor {
-                        if let Some(b) = e.cur_buf {
-                            if let Err(_i) = self.move_down(term, b) {
-                                continue;
-                            }
-                        }
+            }
+            _ => None,
+        };
+        if let Some(op) = op {
+            let reg = mem::take(&mut self.pending).register;
+            return self.visual_operator(term, op, reg);
+        }
+
+        match key {
+            Key::Esc => {
+                self.pending = Pending::default();
+                self.end_visual();
+                self.clamp_cursor();
+                self.view(term)
+            }
+            Key::Char('v') | Key::Char('V') | Key::Ctrl('v') => {
+                let kind = match key {
+                    Key::Char('v') => Kind::Char,
+                    Key::Char('V') => Kind::Line,
+                    _ => Kind::Block,
+                };
+                // The same key again stops selecting
+                match &mut self.visual {
+                    Some(sel) if sel.kind != kind => sel.kind = kind,
+                    _ => {
+                        self.end_visual();
                     }
                 }
-                _ => continue,
+                self.view(term)
+            }
+            // Jump to the other end
+            Key::Char('o') => {
+                let cursor = self.cursor_char();
+                if let Some(sel) = &mut self.visual {
+                    let anchor = mem::replace(&mut sel.anchor, cursor);
+                    self.set_cursor_char(anchor);
+                    self.remember_col();
+                }
+                self.refresh(term)
+            }
+            Key::Char(c @ 'i') | Key::Char(c @ 'a') => {
+                self.pending.object = Some(c == 'i');
+                Ok(())
             }
+            Key::Char('I') | Key::Char('A') => self.visual_insert(term, key == Key::Char('A')),
+            Key::Char(':') => {
+                self.end_visual();
+                self.enter_command(term, ':');
+                self.cmdline.push_str("'<,'>");
+                self.draw_cmdline(term);
+                self.place_cursor(term);
+                Ok(())
+            }
+            _ => {
+                self.normal_key(term, key)?;
+                // Operators act on the selection right away instead
+                self.pending.op = None;
+                Ok(())
+            }
+        }
+    }
+
+    fn visual_operator<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        op: Operator,
+        reg: Option<char>,
+    ) -> super::ZedError {
+        let sel = match self.end_visual() {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let (range, linewise) = match self.cur_buf() {
+            Some(buf) => sel.range(buf),
+            None => return Ok(()),
+        };
+        if sel.kind == Kind::Block {
+            return self.block_operator(term, op, sel, reg);
+        }
+
+        // The cursor goes to the start, like after `d}`
+        self.set_cursor_char(range.start);
+        if op == Operator::Yank && linewise {
+            self.current_index = 1;
         }
+        // `.` acts on as many lines or chars
+        let (target, count) = match self.cur_buf() {
+            Some(buf) if linewise => {
+                let (first, last) = sel.lines(buf);
+                (Span::Lines, last - first + 1)
+            }
+            _ => (Span::Chars, range.len()),
+        };
+        let _ = self.operator_target(term, op, target, count, reg);
         Ok(())
     }
 
-    fn render<T: Write>(
+    // Operators on a block act on every line of it
+    fn block_operator<T: Write>(
         &mut self,
         term: &mut Terminal<T>,
-        keys: KeyIterator,
-    ) -> Result<Self::WidgetReturn, Error> {
-        self.view(term).unwrap();
+        op: Operator,
+        sel: Selection,
+        reg: Option<char>,
+    ) -> super::ZedError {
+        let buf = match self.cur_buf() {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+        let (first, last) = sel.lines(buf);
+        if let Operator::Indent | Operator::Dedent = op {
+            let range = motion::line_range(buf, first, last);
+            let _ = self.run_operator(term, op, range, true, reg);
+            return Ok(());
+        }
 
-        match self.handle_key(term, keys) {
-            Ok(s) => Ok(()),
-            Err(s) => Err(s),
+        let (left, _) = sel.columns(buf, TAB_WIDTH);
+        let top = motion::char_at_col(buf, first, left, TAB_WIDTH);
+        // Lines too short to reach the block are empty rows of it
+        let spans: Vec<_> = (first..=last)
+            .map(|l| sel.span(buf, l, TAB_WIDTH))
+            .collect();
+
+        let cursor = self.cursor_char();
+        let mut texts = Vec::new();
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.begin_transaction(cursor);
+            // The text is typed again on every line when leaving insert mode
+            let line_op = if op == Operator::Change {
+                Operator::Delete
+            } else {
+                op
+            };
+            // Bottom up, so the spans above stay valid
+            for span in spans.into_iter().rev() {
+                texts.push(match span {
+                    Some(span) => line_op.apply(buf, span, false).0,
+                    None => String::new(),
+                });
+            }
+            if op != Operator::Change {
+                buf.commit_transaction(top);
+            }
+        }
+        texts.reverse();
+
+        if let Operator::Delete | Operator::Change | Operator::Yank = op {
+            let yank = op == Operator::Yank;
+            self.store_register(term, reg, Register::block(&texts), yank);
+        }
+
+        self.set_cursor_char(top);
+        if op == Operator::Change {
+            self.block_insert = Some(BlockInsert {
+                first,
+                last,
+                col: left,
+                start: top,
+            });
+            self.set_mode(Mode::Insert);
+        } else {
+            self.clamp_cursor();
+            self.remember_col();
         }
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // I and A, on a block they insert on every line
+    fn visual_insert<T: Write>(&mut self, term: &mut Terminal<T>, append: bool) -> super::ZedError {
+        let sel = match self.end_visual() {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let buf = match self.cur_buf() {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+
+        let idx = if sel.kind == Kind::Block {
+            let (first, last) = sel.lines(buf);
+            let (left, right) = sel.columns(buf, TAB_WIDTH);
+            let col = match (append, sel.to_end) {
+                (false, _) => left,
+                (true, false) => right + 1,
+                (true, true) => usize::MAX,
+            };
+            let idx = motion::char_at_col(buf, first, col, TAB_WIDTH);
+            self.block_insert = Some(BlockInsert {
+                first,
+                last,
+                col,
+                start: idx,
+            });
+            idx
+        } else {
+            let (range, linewise) = sel.range(buf);
+            match (append, linewise) {
+                (false, _) => range.start,
+                // Before the line ending
+                (true, true) => {
+                    let last = buf.rope.char_to_line(range.end.saturating_sub(1));
+                    motion::line_start(buf, last) + buf.line_len(last)
+                }
+                (true, false) => range.end,
+            }
+        };
+
+        let cursor = self.cursor_char();
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.begin_transaction(cursor);
+        }
+        self.set_cursor_char(idx);
+        self.set_mode(Mode::Insert);
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // Type the text inserted on the first line of a block on the others
+    fn finish_block_insert(&mut self, bi: BlockInsert) {
+        let cursor = self.cursor_char();
+        let buf = match self.cur_buf_mut() {
+            Some(b) => b,
+            None => return,
+        };
+        if cursor <= bi.start {
+            return;
+        }
+        let text = buf.rope.slice(bi.start..cursor).to_string();
+        if text.contains('\n') {
+            return;
+        }
+
+        for line in (bi.first + 1..=bi.last).rev() {
+            let le = motion::line_start(buf, line) + buf.line_len(line);
+            let idx = if bi.col == usize::MAX {
+                le
+            } else {
+                let idx = motion::char_at_col(buf, line, bi.col, TAB_WIDTH);
+                // Short lines are left alone
+                if idx == le && motion::col_at_char(buf, le, TAB_WIDTH) < bi.col {
+                    continue;
+                }
+                idx
+            };
+            buf.insert_str(idx, &text);
+        }
+    }
+
+    // Remember a change for `.`. Changes that end in insert mode are
+    // complete once the typing is done.
+    fn record_change(&mut self, action: Action) {
+        let repeat = Repeat {
+            action,
+            typed: Vec::new(),
+        };
+        match self.cur_buf().map(|b| b.mode) {
+            Some(Mode::Insert) => self.inserting = Some(repeat),
+            _ => self.last_change = Some(repeat),
+        }
+    }
+
+    // `.`, a count replaces the one of the change
+    fn repeat_change<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        count: Option<usize>,
+    ) -> MoveResult {
+        let last = match self.last_change.clone() {
+            Some(r) => r,
+            None => return Err(Error::CouldNotMove),
+        };
+
+        match last.action {
+            Action::Operator {
+                op,
+                target,
+                count: c,
+                register,
+            } => self.operator_target(term, op, target, count.unwrap_or(c), register)?,
+            Action::Paste {
+                register,
+                count: c,
+                before,
+            } => {
+                let count = count.unwrap_or(c);
+                self.paste(term, register, count, before)?;
+                self.record_change(Action::Paste {
+                    register,
+                    count,
+                    before,
+                });
+            }
+            Action::Insert { key, count: c } => {
+                self.enter_insert(term, key)?;
+                self.record_change(Action::Insert {
+                    key,
+                    count: count.unwrap_or(c),
+                });
+            }
+        }
+
+        // Type the text again
+        if let Some(Mode::Insert) = self.cur_buf().map(|b| b.mode) {
+            for key in last.typed {
+                self.insert_mode_key(term, key)?;
+            }
+            self.leave_insert(term)?;
+        }
+        Ok(())
+    }
+
+    // Mode switching
+    fn set_mode(&mut self, m: Mode) {
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.mode = m;
+        }
+    }
+
+    // i, a, I, A, o, O
+    fn enter_insert<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> super::ZedError {
+        // The whole insert session is one undo step
+        let cursor = self.cursor_char();
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.begin_transaction(cursor);
+        }
+        self.set_mode(Mode::Insert);
+        match key {
+            Key::Char('a') => {
+                let len = self
+                    .cur_buf()
+                    .map_or(0, |b| b.line_len(self.current_line - 1));
+                if len > 0 {
+                    self.current_index += 1;
+                }
+            }
+            Key::Char('A') => {
+                let len = self
+                    .cur_buf()
+                    .map_or(0, |b| b.line_len(self.current_line - 1));
+                self.current_index = len + 1;
+            }
+            Key::Char('I') => {
+                let line = self.current_line - 1;
+                let idx = self.cur_buf().map(|b| motion::first_non_blank(b, line));
+                if let Some(idx) = idx {
+                    self.set_cursor_char(idx);
+                }
+            }
+            Key::Char('o') | Key::Char('O') => self.open_line(key == Key::Char('o')),
+            _ => (),
+        }
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // Start an empty line below or above the cursor and go there
+    fn open_line(&mut self, below: bool) {
+        let mut line = self.current_line - 1;
+        if let Some(buf) = self.cur_buf_mut() {
+            if below {
+                let idx = buf.rope.line_to_char(line) + buf.line_len(line);
+                buf.split_line(idx);
+                line += 1;
+            } else {
+                let idx = buf.rope.line_to_char(line);
+                buf.split_line(idx);
+            }
+        }
+        self.current_line = line + 1;
+        self.current_index = 1;
+    }
+
+    fn leave_insert<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        if let Some(bi) = self.block_insert.take() {
+            self.finish_block_insert(bi);
+        }
+        if let Some(r) = self.inserting.take() {
+            // `3ifoo` types foo twice more, `3ofoo` on two more new lines
+            if let Action::Insert { key, count } = r.action {
+                for _ in 1..count {
+                    if let Key::Char('o') | Key::Char('O') = key {
+                        self.open_line(true);
+                    }
+                    for k in r.typed.iter() {
+                        self.insert_mode_key(term, *k)?;
+                    }
+                }
+            }
+            self.last_change = Some(r);
+        }
+        let cursor = self.cursor_char();
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.commit_transaction(cursor);
+        }
+        self.set_mode(Mode::Normal);
+        if self.current_index > 1 {
+            self.current_index -= 1;
+        }
+        self.remember_col();
+        self.view(term)
+    }
+
+    // Ctrl-s
+    fn save<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        let backup = self.editor.as_ref().map(|e| e.backup).unwrap_or(false);
+        match self.cur_buf_mut() {
+            Some(buf) => {
+                if buf.save(backup).is_err() {
+                    return Err(Error::CouldNotSave);
+                }
+            }
+            None => return Ok(()),
+        }
+        self.view(term)
+    }
+
+    // Ctrl-q
+    // Fails if there are unsaved buffers, unless `force` is set, which
+    // throws them away without saving on exit
+    fn quit(&mut self, force: bool) -> super::ZedError {
+        if force {
+            return Ok(());
+        }
+        if let Some(e) = &mut self.editor {
+            if e.save_on_exit && e.save_all().is_err() {
+                return Err(Error::CouldNotSave);
+            }
+            if !e.modified_buffers().is_empty() {
+                return Err(Error::UnsavedChanges);
+            }
+        }
+        Ok(())
+    }
+
+    // Ask before throwing away unsaved work, the next key answers
+    fn confirm_quit<T: Write>(&mut self, term: &mut Terminal<T>) {
+        let names: Vec<String> = match &self.editor {
+            Some(e) => e
+                .modified_buffers()
+                .iter()
+                .map(|b| b.display_name())
+                .collect(),
+            None => return,
+        };
+        // Say why saving on exit didn't help
+        let why = match &self.message {
+            Some((m, true)) => format!(" {}.", m),
+            _ => String::new(),
+        };
+        let msg = format!(
+            "{} Unsaved changes in {}. Quit anyway? (y/n) ",
+            why,
+            names.join(", ")
+        );
+        draw_prompt(term, &msg);
+        self.asking = Some(Ask::Quit);
+    }
+
+    // The key after a y/n question. Returns true when the editor should exit.
+    fn answer<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        ask: Ask,
+        key: Key,
+    ) -> Result<bool, Error> {
+        if key != Key::Char('y') {
+            self.view(term)?;
+            return Ok(false);
+        }
+        match ask {
+            Ask::Quit => return Ok(self.quit(true).is_ok()),
+            Ask::Close(id) => self.change_buffer(term, |e| {
+                let _ = e.close(id, true);
+            })?,
+        }
+        // Nothing left to edit
+        Ok(self.cur_buf().is_none())
+    }
+
+    // Switch buffers, remembering where the cursor was in each
+    fn change_buffer<T: Write, F: FnOnce(&mut editor::Editor)>(
+        &mut self,
+        term: &mut Terminal<T>,
+        f: F,
+    ) -> super::ZedError {
+        let pos = (self.current_line, self.current_index, self.shown_lines.0);
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.last_cursor = pos;
+        }
+        if let Some(e) = &mut self.editor {
+            f(e);
+        }
+        self.restore_cursor();
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // Back to where the cursor was when the current buffer was last shown
+    fn restore_cursor(&mut self) {
+        if let Some((line, index, first)) = self.cur_buf().map(|b| b.last_cursor) {
+            self.current_line = line;
+            self.current_index = index;
+            self.shown_lines.0 = first;
+        }
+        self.shown_col = 0;
+        self.clamp_cursor();
+        self.remember_col();
+    }
+
+    // SPC f
+    fn open_file<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> super::ZedError {
+        let dir = match self.editor.as_ref().and_then(|e| e.dir.clone()) {
+            Some(d) => d,
+            None => PathBuf::from("."),
+        };
+        self.clear(term);
+        let mut finder = Picker::new().set_source(FileSource::default().set_dir(dir));
+        match finder.render(term, keys) {
+            Ok(Some(Target::File(p))) => self.change_buffer(term, |e| {
+                e.open(p);
+            }),
+            _ => self.view(term),
+        }
+    }
+
+    // SPC r, replace in every file under the editor's dir
+    // Open buffers are changed in place, other files are saved.
+    // Either way each file gets one undo step.
+    fn project_replace<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> super::ZedError {
+        let (dir, open, backup) = match &self.editor {
+            Some(e) => (
+                e.dir.clone().unwrap_or_else(|| PathBuf::from(".")),
+                e.buffers
+                    .iter()
+                    .filter_map(|b| Some((b.p.clone()?, b.rope.to_string())))
+                    .collect(),
+                e.backup,
+            ),
+            None => return Ok(()),
+        };
+        let mut panel = ReplacePanel::new().set_dir(dir).set_open(open);
+        self.clear(term);
+        let files = panel.render(term, keys)?;
+
+        let mut count = 0;
+        let mut changed = 0;
+        // Files left alone, and why
+        let mut failed = Vec::new();
+        for file in files.iter() {
+            let id = self.editor.as_ref().and_then(|e| e.find_path(&file.path));
+            let result = match self
+                .editor
+                .as_mut()
+                .zip(id)
+                .and_then(|(e, id)| e.get_mut(id))
+            {
+                // Edits are char ranges into the text searched
+                Some(buf) if buf.content_hash() != file.hash => Err("changed since the search"),
+                Some(buf) => {
+                    replace::apply(buf, &file.edits);
+                    Ok(())
+                }
+                None => {
+                    let mut buf = Buffer::new().set_path(file.path.clone());
+                    if buf.content_hash() != file.hash {
+                        Err("changed since the search")
+                    } else {
+                        replace::apply(&mut buf, &file.edits);
+                        buf.save(backup).map_err(|_| "could not save")
+                    }
+                }
+            };
+            match result {
+                Ok(()) => {
+                    count += file.edits.len();
+                    changed += 1;
+                }
+                Err(why) => failed.push(format!("{} ({})", file.path.display(), why)),
+            }
+        }
+        if !files.is_empty() {
+            let mut msg = format!("{} replacements in {} files", count, changed);
+            if !failed.is_empty() {
+                msg.push_str(&format!(", not in {}", failed.join(", ")));
+            }
+            self.message = Some((msg, !failed.is_empty()));
+        }
+
+        self.clamp_cursor();
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // SPC x
+    // Unsaved buffers are only closed once the question is answered
+    fn close_buffer<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        let id = match self.editor.as_ref().and_then(|e| e.cur_buf) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let modified = self.cur_buf().map(|b| b.is_modified()).unwrap_or(false);
+        if modified {
+            let name = self.cur_buf().map(|b| b.display_name()).unwrap_or_default();
+            draw_prompt(
+                term,
+                &format!(" Unsaved changes in {}. Close anyway? (y/n) ", name),
+            );
+            self.asking = Some(Ask::Close(id));
+            return Ok(());
+        }
+        self.change_buffer(term, |e| {
+            let _ = e.close(id, true);
+        })
+    }
+
+    // SPC b
+    fn pick_buffer<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> super::ZedError {
+        let list = match &self.editor {
+            Some(e) => e.list(),
+            None => return Ok(()),
+        };
+        self.clear(term);
+        let mut picker = Picker::new().set_source(BufferSource::default().set_buffers(list));
+        match picker.render(term, keys) {
+            Ok(Some(id)) => self.change_buffer(term, |e| {
+                e.switch_to(id);
+            }),
+            _ => self.view(term),
+        }
+    }
+
+    // SPC :, pick an ex command
+    // Commands that need arguments go to the command line to type them
+    fn pick_command<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> Result<bool, Error> {
+        self.clear(term);
+        let mut picker = Picker::new().set_source(CommandSource::default().set_commands(COMMANDS));
+        let name = match picker.render(term, keys) {
+            Ok(Some(name)) => name,
+            _ => {
+                self.view(term)?;
+                return Ok(false);
+            }
+        };
+        self.view(term)?;
+        if matches!(name, "edit" | "buffer" | "substitute") {
+            self.enter_command(term, ':');
+            self.cmdline = format!("{} ", name);
+            self.draw_cmdline(term);
+            self.place_cursor(term);
+            return Ok(false);
+        }
+        match self.execute(term, name) {
+            Ok(true) => return Ok(true),
+            Ok(false) => (),
+            Err(err) => self.message = Some((format!("{}", err), true)),
+        }
+        self.clamp_cursor();
+        self.scroll(term);
+        self.view(term)?;
+        Ok(false)
+    }
+
+    // Tab and SPC t
+    fn tab_action<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        action: TabAction,
+    ) -> super::ZedError {
+        match action {
+            TabAction::Next => self.change_buffer(term, |e| e.next_buf()),
+            TabAction::Prev => self.change_buffer(term, |e| e.prev_buf()),
+            TabAction::Close => self.close_buffer(term),
+            TabAction::Goto(n) => match self.tabbar.tab_id(n) {
+                Some(id) => self.change_buffer(term, |e| {
+                    e.switch_to(id);
+                }),
+                None => Ok(()),
+            },
+            TabAction::Nothing => Ok(()),
+        }
+    }
+
+    // Bottom row, the command being typed or the last message
+    // Clear the screen, the next view draws every row
+    fn clear<T: Write>(&mut self, term: &mut Terminal<T>) {
+        term.clear_screen().unwrap();
+        self.drawn.clear();
+        self.drawn_size = term.get_size();
+    }
+
+    fn draw_cmdline<T: Write>(&self, term: &mut Terminal<T>) {
+        let (x, _) = term.get_size();
+        let (text, error) = match (self.cur_buf().map(|b| b.mode), &self.message) {
+            (Some(Mode::Command), _) => (format!("{}{}", self.prompt, self.cmdline), false),
+            _ if self.confirm.is_some() => {
+                let with = self.confirm.as_ref().and_then(|c| c.current());
+                let with = with.map_or("", |(_, t)| t).replace('\n', "^M");
+                (format!("replace with {} (y/n/a/q)?", with), true)
+            }
+            (_, Some((msg, error))) => (msg.clone(), *error),
+            _ => match &self.recording {
+                Some((name, _)) => (format!("recording @{}", name), false),
+                None => (String::new(), false),
+            },
+        };
+
+        term.set_cursor_to(1, term.rel_size.1).unwrap();
+        if error {
+            term.print(fg(Color::Red)).unwrap();
+        }
+        let mut line: String = text.chars().take(x as usize).collect();
+        while line.chars().count() < x as usize {
+            line.push(' ');
+        }
+        term.print(line).unwrap();
+        term.print(fg(Color::Reset)).unwrap();
+    }
+
+    // `:`, or `/` and `?` to search
+    fn enter_command<T: Write>(&mut self, term: &mut Terminal<T>, prompt: char) {
+        self.cmdline.clear();
+        self.prompt = prompt;
+        self.search_start = (self.current_line, self.current_index, self.shown_lines.0);
+        self.set_mode(Mode::Command);
+        if let Some(e) = &mut self.editor {
+            e.cmd_history.reset();
+            e.search_history.reset();
+        }
+        self.draw_cmdline(term);
+        self.place_cursor(term);
+    }
+
+    // Command-mode keys. Returns true when the editor should exit.
+    fn command_key<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> Result<bool, Error> {
+        if self.prompt != ':' {
+            self.search_key(term, key)?;
+            return Ok(false);
+        }
+        match key {
+            Key::Esc => {
+                self.set_mode(Mode::Normal);
+                self.draw_cmdline(term);
+                self.place_cursor(term);
+                return Ok(false);
+            }
+            Key::Enter => {
+                let line = self.cmdline.clone();
+                self.set_mode(Mode::Normal);
+                if let Some(e) = &mut self.editor {
+                    e.cmd_history.push(&line);
+                    let _ = e.cmd_history.save();
+                }
+                match self.execute(term, &line) {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => (),
+                    Err(err) => self.message = Some((format!("{}", err), true)),
+                }
+                self.clamp_cursor();
+                self.scroll(term);
+                self.view(term)?;
+                return Ok(false);
+            }
+            Key::Backspace => {
+                if self.cmdline.is_empty() {
+                    self.set_mode(Mode::Normal);
+                } else {
+                    self.cmdline.pop();
+                }
+            }
+            Key::Up | Key::Down => {
+                let entry = match &mut self.editor {
+                    Some(e) if key == Key::Up => e.cmd_history.prev().map(|s| s.to_string()),
+                    Some(e) => e.cmd_history.next().map(|s| s.to_string()),
+                    None => None,
+                };
+                self.cmdline = entry.unwrap_or_default();
+            }
+            Key::Char(c) => self.cmdline.push(c),
+            _ => (),
+        }
+        self.draw_cmdline(term);
+        self.place_cursor(term);
+        Ok(false)
+    }
+
+    // Keys typed after `/` and `?`
+    // The cursor jumps to the first match while typing
+    fn search_key<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> super::ZedError {
+        let forward = self.prompt == '/';
+        match key {
+            Key::Esc => self.set_mode(Mode::Normal),
+            Key::Enter => {
+                self.set_mode(Mode::Normal);
+                self.back_to_search_start();
+                // An empty pattern repeats the last search
+                let pattern = match self.last_search() {
+                    Some((p, _)) if self.cmdline.is_empty() => p,
+                    _ => self.cmdline.clone(),
+                };
+                if !pattern.is_empty() {
+                    if let Some(e) = &mut self.editor {
+                        e.search_history.push(&pattern);
+                        let _ = e.search_history.save();
+                    }
+                    self.search = Some((pattern, forward));
+                    if let Err(err) = self.search_next(term, false, 1) {
+                        self.message = Some((format!("{}", err), true));
+                    }
+                }
+                self.scroll(term);
+                return self.view(term);
+            }
+            Key::Backspace if self.cmdline.is_empty() => self.set_mode(Mode::Normal),
+            Key::Backspace => {
+                self.cmdline.pop();
+            }
+            Key::Up | Key::Down => {
+                let entry = match &mut self.editor {
+                    Some(e) if key == Key::Up => e.search_history.prev().map(|s| s.to_string()),
+                    Some(e) => e.search_history.next().map(|s| s.to_string()),
+                    None => None,
+                };
+                self.cmdline = entry.unwrap_or_default();
+            }
+            Key::Char(c) => self.cmdline.push(c),
+            _ => (),
+        }
+
+        self.back_to_search_start();
+        let searching = matches!(self.cur_buf().map(|b| b.mode), Some(Mode::Command));
+        if let (true, Ok(re)) = (searching, search::compile(&self.cmdline)) {
+            let found = self
+                .cur_buf()
+                .and_then(|buf| search::find(buf, &re, self.cursor_char(), forward));
+            if let Some((range, _)) = found {
+                self.set_cursor_char(range.start);
+            }
+        }
+        self.clamp_cursor();
+        self.scroll(term);
+        self.view(term)
+    }
+
+    fn back_to_search_start(&mut self) {
+        let (line, index, first) = self.search_start;
+        self.current_line = line;
+        self.current_index = index;
+        self.shown_lines.0 = first;
+    }
+
+    // The last search, or the newest one in the history of an earlier session
+    fn last_search(&self) -> Option<(String, bool)> {
+        if let Some(s) = &self.search {
+            return Some(s.clone());
+        }
+        let e = self.editor.as_ref()?;
+        e.search_history.entries.last().map(|p| (p.clone(), true))
+    }
+
+    // n and N, `reverse` goes the other way than the search did
+    fn search_next<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        reverse: bool,
+        count: usize,
+    ) -> MoveResult {
+        let (pattern, forward) = match self.last_search() {
+            Some(s) => s,
+            None => return Err(Error::NoPattern),
+        };
+        let forward = forward != reverse;
+        let re = search::compile(&pattern)?;
+        let buf = match self.cur_buf() {
+            Some(b) => b,
+            None => return Err(Error::CouldNotMove),
+        };
+
+        let mut idx = self.cursor_char();
+        let mut wrapped = false;
+        for _ in 0..count {
+            match search::find(buf, &re, idx, forward) {
+                Some((range, w)) => {
+                    idx = range.start;
+                    wrapped |= w;
+                }
+                None => return Err(Error::PatternNotFound { pattern }),
+            }
+        }
+
+        self.message = Some(match (wrapped, forward) {
+            (false, true) => (format!("/{}", pattern), false),
+            (false, false) => (format!("?{}", pattern), false),
+            (true, true) => ("search hit BOTTOM, continuing at TOP".to_string(), true),
+            (true, false) => ("search hit TOP, continuing at BOTTOM".to_string(), true),
+        });
+        self.motion_to(term, idx)?;
+        self.draw_cmdline(term);
+        self.place_cursor(term);
+        Ok(())
+    }
+
+    // * and #, search for the word under the cursor
+    fn search_word<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        forward: bool,
+        count: usize,
+    ) -> MoveResult {
+        let (pattern, start) = match self
+            .cur_buf()
+            .and_then(|b| search::word_pattern(b, self.cursor_char()))
+        {
+            Some(p) => p,
+            None => return Err(Error::CouldNotMove),
+        };
+        if let Some(e) = &mut self.editor {
+            e.search_history.push(&pattern);
+            let _ = e.search_history.save();
+        }
+        self.search = Some((pattern, forward));
+        // Start at the word so # skips it
+        self.set_cursor_char(start);
+        self.search_next(term, false, count)
+    }
+
+    // Run an ex command. Returns true when the editor should exit.
+    fn execute<T: Write>(&mut self, term: &mut Terminal<T>, line: &str) -> Result<bool, Error> {
+        let cmd = Command::parse(line)?;
+        let range = match (&cmd.range, self.cur_buf()) {
+            (Some(r), Some(buf)) => Some(r.resolve(buf, self.current_line)?),
+            _ => None,
+        };
+
+        match cmd.name.as_str() {
+            // `:42` jumps to a line
+            "" => {
+                if let Some((_, end)) = range {
+                    self.current_line = end.max(1);
+                    self.current_index = 1;
+                }
+            }
+            "w" | "write" => self.write(&cmd.args)?,
+            "wq" | "x" | "xit" => {
+                self.write(&cmd.args)?;
+                return self.quit_window(cmd.bang);
+            }
+            "q" | "quit" => return self.quit_window(cmd.bang),
+            "close" | "clo" => self.close_window(),
+            "only" | "on" => self.only_window(),
+            "qa" | "qall" | "quitall" => {
+                self.quit(cmd.bang)?;
+                return Ok(true);
+            }
+            "wa" | "wall" => self.write_all()?,
+            "wqa" | "wqall" | "xa" | "xall" => {
+                self.write_all()?;
+                self.quit(cmd.bang)?;
+                return Ok(true);
+            }
+            "e" | "edit" => {
+                if cmd.args.is_empty() {
+                    return Err(Error::MissingArgument);
+                }
+                let p = PathBuf::from(&cmd.args);
+                self.change_buffer(term, |e| {
+                    e.open(p);
+                })?;
+            }
+            "sp" | "split" | "vs" | "vsplit" => {
+                if cmd.name.starts_with('v') {
+                    self.split_window(SplitDir::Vertical);
+                } else {
+                    self.split_window(SplitDir::Horizontal);
+                }
+                if !cmd.args.is_empty() {
+                    let p = PathBuf::from(&cmd.args);
+                    self.change_buffer(term, |e| {
+                        e.open(p);
+                    })?;
+                }
+            }
+            "bn" | "bnext" => self.change_buffer(term, |e| e.next_buf())?,
+            "bp" | "bprevious" | "bN" | "bNext" => self.change_buffer(term, |e| e.prev_buf())?,
+            "bd" | "bdelete" => {
+                let id = match self.editor.as_ref().and_then(|e| e.cur_buf) {
+                    Some(id) => id,
+                    None => return Ok(false),
+                };
+                let mut result = Ok(());
+                self.change_buffer(term, |e| result = e.close(id, cmd.bang))?;
+                result?;
+                if self.cur_buf().is_none() {
+                    return Ok(true);
+                }
+            }
+            "b" | "buffer" => {
+                let n: usize = cmd.args.parse().map_err(|_| Error::MissingArgument)?;
+                match self.tabbar.tab_id(n) {
+                    Some(id) => self.change_buffer(term, |e| {
+                        e.switch_to(id);
+                    })?,
+                    None => return Err(Error::InvalidRange),
+                }
+            }
+            "ls" | "buffers" => {
+                let list = match &self.editor {
+                    Some(e) => e.list(),
+                    None => Vec::new(),
+                };
+                let current = self.editor.as_ref().and_then(|e| e.cur_buf);
+                let entries: Vec<String> = list
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (id, name, modified))| {
+                        format!(
+                            "{}{}{} {}",
+                            i + 1,
+                            if current == Some(*id) { "%" } else { "" },
+                            if *modified { "+" } else { "" },
+                            name
+                        )
+                    })
+                    .collect();
+                self.message = Some((entries.join("  "), false));
+            }
+            // Delete lines
+            // `:d x` keeps them in register x
+            "d" | "delete" => {
+                let (start, end) = range.unwrap_or((self.current_line, self.current_line));
+                let mut text = String::new();
+                if let Some(buf) = self.cur_buf_mut() {
+                    let lines = motion::line_range(buf, start - 1, end - 1);
+                    text = buf.rope.slice(lines.clone()).to_string();
+                    buf.delete(lines);
+                }
+                let name = cmd.args.chars().next();
+                self.store_register(term, name, Register::new(text, true), false);
+                self.current_line = start;
+                self.current_index = 1;
+                let lines = self.cur_buf().map_or(1, |b| b.line_count).max(1);
+                self.current_line = self.current_line.min(lines);
+            }
+            "s" | "substitute" => {
+                let (start, end) = range.unwrap_or((self.current_line, self.current_line));
+                self.substitute(&cmd.args, start - 1, end - 1)?;
+            }
+            _ => return Err(Error::UnknownCommand { cmd: cmd.name }),
+        }
+        Ok(false)
+    }
+
+    // :s, on lines `first..=last` (0-based)
+    // Every replacement is one undo step, even when confirmed one by one
+    fn substitute(&mut self, args: &str, first: usize, last: usize) -> super::ZedError {
+        let mut sub = Substitute::parse(args)?;
+        // An empty pattern is the last search
+        if sub.pattern.is_empty() {
+            sub.pattern = match self.last_search() {
+                Some((p, _)) => p,
+                None => return Err(Error::NoPattern),
+            };
+        }
+        let re = sub.regex()?;
+        let matches = match self.cur_buf() {
+            Some(buf) => sub.matches(&re, buf, first, last),
+            None => return Ok(()),
+        };
+        if matches.is_empty() {
+            return Err(Error::PatternNotFound {
+                pattern: sub.pattern,
+            });
+        }
+        self.search = Some((sub.pattern, true));
+
+        let cursor = self.cursor_char();
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.begin_transaction(cursor);
+        }
+        self.confirm = Some(Confirm {
+            matches,
+            next: 0,
+            shift: 0,
+            done: 0,
+            cursor,
+        });
+        if sub.confirm {
+            self.show_confirm();
+        } else {
+            while self.confirm_step(true) {}
+            self.finish_substitute();
+        }
+        Ok(())
+    }
+
+    // Replace the next match of `:s` or skip it
+    // Returns false when there are no matches left
+    fn confirm_step(&mut self, replace: bool) -> bool {
+        let mut c = match self.confirm.take() {
+            Some(c) => c,
+            None => return false,
+        };
+        let (range, text) = match c.current() {
+            Some((r, t)) => (r, t.to_string()),
+            None => {
+                self.confirm = Some(c);
+                return false;
+            }
+        };
+        if replace {
+            if let Some(buf) = self.cur_buf_mut() {
+                buf.delete(range.clone());
+                buf.insert_str(range.start, &text);
+            }
+            c.shift += text.chars().count() as isize - range.len() as isize;
+            c.done += 1;
+            c.cursor = range.start;
+        }
+        c.next += 1;
+        let more = c.next < c.matches.len();
+        self.confirm = Some(c);
+        more
+    }
+
+    // Put the cursor on the match being asked about
+    fn show_confirm(&mut self) {
+        let start = self
+            .confirm
+            .as_ref()
+            .and_then(|c| c.current())
+            .map(|(r, _)| r.start);
+        if let Some(start) = start {
+            self.set_cursor_char(start);
+        }
+    }
+
+    // End a `:s`, the cursor goes to the line of the last replacement
+    fn finish_substitute(&mut self) {
+        let c = match self.confirm.take() {
+            Some(c) => c,
+            None => return,
+        };
+        let cursor = match self.cur_buf_mut() {
+            Some(buf) => {
+                let line = buf.rope.char_to_line(c.cursor.min(buf.rope.len_chars()));
+                let idx = motion::first_non_blank(buf, line);
+                buf.commit_transaction(idx);
+                idx
+            }
+            None => return,
+        };
+        self.set_cursor_char(cursor);
+        self.remember_col();
+        self.message = match c.done {
+            0 => None,
+            1 => Some(("1 substitution".to_string(), false)),
+            n => Some((format!("{} substitutions", n), false)),
+        };
+    }
+
+    // y/n/a/q while confirming `:s///c`
+    fn confirm_key<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> super::ZedError {
+        let more = match key {
+            Key::Char('y') => self.confirm_step(true),
+            Key::Char('n') => self.confirm_step(false),
+            Key::Char('a') => {
+                while self.confirm_step(true) {}
+                false
+            }
+            Key::Char('q') | Key::Esc => false,
+            _ => return Ok(()),
+        };
+        if more {
+            self.show_confirm();
+        } else {
+            self.finish_substitute();
+        }
+        self.clamp_cursor();
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // :w and :w file
+    fn write(&mut self, args: &str) -> super::ZedError {
+        let backup = self.editor.as_ref().map(|e| e.backup).unwrap_or(false);
+        let buf = match self.cur_buf_mut() {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+        let saved = if args.is_empty() {
+            if buf.p.is_none() {
+                return Err(Error::NoFileName);
+            }
+            buf.save(backup)
+        } else {
+            buf.save_as(PathBuf::from(args), backup)
+        };
+        if saved.is_err() {
+            return Err(Error::CouldNotSave);
+        }
+        let msg = format!("\"{}\" {}L written", buf.display_name(), buf.line_count);
+        self.message = Some((msg, false));
+        Ok(())
+    }
+
+    fn write_all(&mut self) -> super::ZedError {
+        if let Some(e) = &mut self.editor {
+            if e.save_all().is_err() {
+                return Err(Error::CouldNotSave);
+            }
+        }
+        Ok(())
+    }
+
+    // :q closes the window, or the editor when it is the last one
+    fn quit_window(&mut self, force: bool) -> Result<bool, Error> {
+        if self.layout.windows().len() > 1 {
+            self.close_window();
+            return Ok(false);
+        }
+        self.quit(force)?;
+        Ok(true)
+    }
+
+    // u and Ctrl-r
+    fn undo<T: Write>(&mut self, term: &mut Terminal<T>, redo: bool) -> super::ZedError {
+        let cursor = match self.cur_buf_mut() {
+            Some(buf) if redo => buf.redo(),
+            Some(buf) => buf.undo(),
+            None => None,
+        };
+        if let Some(c) = cursor {
+            let len = self.cur_buf().map_or(0, |b| b.rope.len_chars());
+            self.set_cursor_char(c.min(len));
+            self.clamp_cursor();
+            self.scroll(term);
+            self.view(term)?;
+        }
+        Ok(())
+    }
+
+    // Ctrl-r in insert mode
+    fn insert_register<T: Write>(&mut self, term: &mut Terminal<T>, name: char) -> super::ZedError {
+        let text = match self.editor.as_ref().and_then(|e| e.registers.get(name)) {
+            Some(r) => r.text.clone(),
+            None => return Ok(()),
+        };
+        let idx = self.cursor_char();
+        if let Some(buf) = self.cur_buf_mut() {
+            buf.insert_str(idx, &text);
+        }
+        self.set_cursor_char(idx + text.chars().count());
+        self.remember_col();
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // Keys typed in insert mode, kept for `.`
+    fn insert_mode_key<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> super::ZedError {
+        if key != Key::Esc {
+            if let Some(r) = &mut self.inserting {
+                r.typed.push(key);
+            }
+        }
+
+        // Ctrl-r pastes the register typed after it
+        if self.pending.quote {
+            self.pending.quote = false;
+            if let Key::Char(c) = key {
+                self.insert_register(term, c)?;
+            }
+            return Ok(());
+        }
+        match key {
+            Key::Esc => self.leave_insert(term),
+            Key::Ctrl('r') => {
+                self.pending.quote = true;
+                Ok(())
+            }
+            _ => self.insert_key(term, key),
+        }
+    }
+
+    // Insert-mode keys
+    fn insert_key<T: Write>(&mut self, term: &mut Terminal<T>, key: Key) -> super::ZedError {
+        let idx = self.cursor_char();
+        let mut new_idx = idx;
+        let buf = match self.cur_buf_mut() {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+
+        match key {
+            Key::Char(c) => {
+                buf.insert_char(idx, c);
+                new_idx += 1;
+            }
+            Key::Tab => {
+                buf.insert_char(idx, '\t');
+                new_idx += 1;
+            }
+            Key::Enter => {
+                buf.split_line(idx);
+                new_idx += 1;
+            }
+            Key::Backspace => {
+                if idx == 0 {
+                    return Ok(());
+                }
+                // Also joins lines when at the start of one
+                buf.delete(idx - 1..idx);
+                new_idx -= 1;
+            }
+            _ => return Ok(()),
+        }
+        self.set_cursor_char(new_idx);
+        self.remember_col();
+
+        self.scroll(term);
+        self.view(term)
+    }
+
+    // The `q` that ends a recording
+    fn stop_recording<T: Write>(&mut self, term: &mut Terminal<T>) {
+        if let Some((name, mut typed)) = self.recording.take() {
+            if typed.last() == Some(&Key::Char('q')) {
+                typed.pop();
+            }
+            if let Some(e) = &mut self.editor {
+                e.registers
+                    .set(name, Register::new(keys::to_text(&typed), false));
+            }
+        }
+        self.draw_cmdline(term);
+        self.place_cursor(term);
+    }
+
+    // @{reg} runs the keys kept in a register, @@ the last one run
+    // Keys typed into popups, like the finder after `SPC f`, come from
+    // the terminal and not the macro
+    fn run_macro<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        name: char,
+        count: usize,
+        keys: KeyIterator,
+    ) -> Result<bool, Error> {
+        let name = match name {
+            '@' => match self.last_macro {
+                Some(n) => n,
+                None => return Ok(false),
+            },
+            n => n,
+        };
+        let reg = match self.editor.as_ref().and_then(|e| e.registers.get(name)) {
+            Some(r) => r.clone(),
+            None => return Ok(false),
+        };
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            return Ok(false);
+        }
+        self.last_macro = Some(name);
+
+        // A macro yanked back with `yy` ends in a line ending
+        let mut text = reg.text;
+        if reg.linewise && text.ends_with('\n') {
+            text.pop();
+        }
+        let macro_keys = keys::from_text(&text);
+
+        self.macro_depth += 1;
+        let mut quit = false;
+        'outer: for _ in 0..count {
+            for key in macro_keys.iter() {
+                if self.process_key(term, *key, keys.clone())? {
+                    quit = true;
+                    break 'outer;
+                }
+            }
+        }
+        self.macro_depth -= 1;
+        Ok(quit)
+    }
+
+    // The key after SPC. Returns true when the editor should exit.
+    fn space_key<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        key: Key,
+        keys: KeyIterator,
+    ) -> Result<bool, Error> {
+        match key {
+            Key::Char('f') => self.open_file(term, keys).unwrap(),
+            Key::Char('n') => self.change_buffer(term, |e| e.next_buf()).unwrap(),
+            Key::Char('p') => self.change_buffer(term, |e| e.prev_buf()).unwrap(),
+            Key::Char('b') => self.pick_buffer(term, keys).unwrap(),
+            Key::Char(':') => return self.pick_command(term, keys),
+            Key::Char('r') => self.project_replace(term, keys)?,
+            Key::Char('x') => {
+                self.close_buffer(term).unwrap();
+                // Nothing left to edit
+                return Ok(self.cur_buf().is_none());
+            }
+            Key::Char('t') => self.pending.tab = true,
+            _ => (),
+        }
+        Ok(false)
+    }
+
+    // Handle one key, typed or replayed from a macro. Returns true when
+    // the editor should exit.
+    fn process_key<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        key: Key,
+        keys: KeyIterator,
+    ) -> Result<bool, Error> {
+        let mode = match self.cur_buf() {
+            Some(b) => b.mode,
+            None => Mode::Normal,
+        };
+
+        if let Mode::Insert = mode {
+            self.insert_mode_key(term, key).unwrap();
+            return Ok(false);
+        }
+
+        if let Mode::Command = mode {
+            return self.command_key(term, key);
+        }
+
+        if let Mode::Visual = mode {
+            self.visual_key(term, key).unwrap();
+            return Ok(false);
+        }
+
+        if self.confirm.is_some() {
+            self.confirm_key(term, key)?;
+            return Ok(false);
+        }
+
+        if let Some(ask) = self.asking.take() {
+            return self.answer(term, ask, key);
+        }
+
+        // Messages stay up until the next key
+        if self.message.take().is_some() {
+            self.draw_cmdline(term);
+            self.place_cursor(term);
+        }
+
+        // q{reg} and @{reg}
+        if self.pending.record || self.pending.at {
+            let pending = mem::take(&mut self.pending);
+            if let Key::Char(c) = key {
+                if pending.at {
+                    let count = pending.count().unwrap_or(1);
+                    return self.run_macro(term, c, count, keys);
+                }
+                if Registers::is_valid(c) {
+                    self.recording = Some((c, Vec::new()));
+                    self.draw_cmdline(term);
+                    self.place_cursor(term);
+                }
+            }
+            return Ok(false);
+        }
+
+        // The keys after SPC, Ctrl-w and SPC t
+        if self.pending.space {
+            self.pending = Pending::default();
+            return self.space_key(term, key, keys);
+        }
+        if self.pending.window {
+            self.pending = Pending::default();
+            self.window_command(term, key)?;
+            return Ok(false);
+        }
+        if self.pending.tab {
+            self.pending = Pending::default();
+            self.tab_action(term, tabbar::action(key))?;
+            return Ok(self.cur_buf().is_none());
+        }
+
+        if self.normal_key(term, key)? {
+            return Ok(false);
+        }
+        // Anything else cancels a half typed command
+        if !self.pending.is_empty() {
+            self.pending = Pending::default();
+            return Ok(false);
+        }
+
+        match key {
+            Key::Ctrl('q') => match self.quit(false) {
+                Ok(_) => return Ok(true),
+                Err(Error::UnsavedChanges) => self.confirm_quit(term),
+                Err(err) => {
+                    self.message = Some((format!("{}", err), true));
+                    self.confirm_quit(term);
+                }
+            },
+            // User Space Bindings, the next key says which
+            Key::Char(' ') => self.pending.space = true,
+            Key::Char(':') => self.enter_command(term, ':'),
+            Key::Char('/') => self.enter_command(term, '/'),
+            Key::Char('?') => self.enter_command(term, '?'),
+            Key::Ctrl('w') => self.pending.window = true,
+            Key::Tab => self.tab_action(term, TabAction::Next).unwrap(),
+            Key::Ctrl('s') => {
+                if let Err(err) = self.save(term) {
+                    self.message = Some((format!("{}", err), true));
+                    self.draw_cmdline(term);
+                    self.place_cursor(term);
+                }
+            }
+            Key::Char('v') => self.enter_visual(term, Kind::Char).unwrap(),
+            Key::Char('V') => self.enter_visual(term, Kind::Line).unwrap(),
+            Key::Ctrl('v') => self.enter_visual(term, Kind::Block).unwrap(),
+            Key::Char('u') => self.undo(term, false).unwrap(),
+            Key::Ctrl('r') => self.undo(term, true).unwrap(),
+            _ => return Ok(false),
+        }
+        Ok(false)
+    }
+}
+
+// One line message over the statusline
+fn draw_prompt<T: Write>(term: &mut Terminal<T>, msg: &str) {
+    let (x, _) = term.get_size();
+    term.set_cursor_to(1, term.rel_size.1).unwrap();
+    term.print(color::bg(Color::Red)).unwrap();
+    let mut line: String = msg.chars().take(x as usize).collect();
+    while line.chars().count() < x as usize {
+        line.push(' ');
+    }
+    term.print(line).unwrap();
+    term.print(color::bg(Color::Reset)).unwrap();
+}
+
+// Width of the line number column
+fn number_width(line_count: usize) -> u16 {
+    let mut width = 1;
+    let mut n = line_count / 10;
+    while n > 0 {
+        width += 1;
+        n /= 10;
+    }
+    width
+}
+
+// Screen column of the char at `col`, accounting for tabs
+fn display_col(line: &RopeSlice, col: usize) -> usize {
+    let mut width = 0;
+    for c in line.chars().take(col) {
+        if c == '\t' {
+            width += TAB_WIDTH - width % TAB_WIDTH;
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+// Paste the rows of a block one below the other, from the column of the
+// cursor. Rows are padded to the same width when text follows them, and
+// lines are added past the end of the buffer. Returns the new cursor.
+fn paste_block(buf: &mut Buffer, cursor: usize, text: &str, count: usize, before: bool) -> usize {
+    let line = buf.rope.char_to_line(cursor);
+    let ls = motion::line_start(buf, line);
+    let col = if before || buf.line_len(line) == 0 {
+        motion::col_at_char(buf, cursor, TAB_WIDTH)
+    } else {
+        motion::col_at_char(buf, (cursor + 1).min(ls + buf.line_len(line)), TAB_WIDTH)
+    };
+    let rows: Vec<&str> = text.split('\n').collect();
+    let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+
+    for (i, row) in rows.iter().enumerate() {
+        let padded = format!("{:width$}", row, width = width);
+        let piece = padded.repeat(count - 1) + row;
+        let l = line + i;
+        let len = buf.rope.len_chars();
+        let ends_open = len > 0 && buf.rope.char(len - 1) != '\n';
+        // Not the empty line after the final line ending
+        let real_lines = buf.line_count - usize::from(!ends_open && len > 0);
+        if l >= real_lines {
+            let new_line = " ".repeat(col) + &piece;
+            if ends_open {
+                buf.insert_str(len, &format!("\n{}", new_line));
+            } else {
+                buf.insert_str(len, &format!("{}\n", new_line));
+            }
+            continue;
+        }
+
+        let le = motion::line_start(buf, l) + buf.line_len(l);
+        let line_width = motion::col_at_char(buf, le, TAB_WIDTH);
+        if line_width < col {
+            buf.insert_str(le, &(" ".repeat(col - line_width) + &piece));
+        } else {
+            let at = motion::char_at_col(buf, l, col, TAB_WIDTH);
+            if at < le {
+                buf.insert_str(at, &padded.repeat(count));
+            } else {
+                buf.insert_str(at, &piece);
+            }
+        }
+    }
+    motion::char_at_col(buf, line, col, TAB_WIDTH)
+}
+
+fn draw_line(
+    line: &RopeSlice,
+    cols: Range<usize>,
+    sel: Option<Range<usize>>,
+    found: &[Range<usize>],
+) -> String {
+    // Expand tabs and show the screen columns `cols` of the line, it
+    // scrolls sideways when the cursor is past the window's edge. Spaces
+    // fill the rest, over what was drawn there before.
+    // The selected chars and search matches, relative to the line start,
+    // are highlighted
+    let highlight = format!("{}", color::bg(Color::RGB(92, 92, 61)));
+    let matched = format!("{}", color::bg(Color::RGB(153, 102, 0)));
+    let reset = format!("{}", color::bg(Color::Reset));
+    let mut out = String::new();
+    let mut width = 0;
+    let mut bg = &reset;
+    for (i, c) in line.chars().enumerate() {
+        if c == '\n' || c == '\r' || width >= cols.end {
+            break;
+        }
+        let w = if c == '\t' {
+            TAB_WIDTH - width % TAB_WIDTH
+        } else {
+            1
+        };
+        // A tab can stick out of either side of the window
+        let shown = (width + w)
+            .min(cols.end)
+            .saturating_sub(cols.start.max(width));
+        width += w;
+        if shown == 0 {
+            continue;
+        }
+
+        let want = if matches!(&sel, Some(s) if s.contains(&i)) {
+            &highlight
+        } else if found.iter().any(|r| r.contains(&i)) {
+            &matched
+        } else {
+            &reset
+        };
+        if want != bg {
+            out.push_str(want);
+            bg = want;
+        }
+        if c == '\t' {
+            for _ in 0..shown {
+                out.push(' ');
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    // A selected empty line still shows
+    let empty = matches!(&sel, Some(sel) if sel.start == 0 && cols.start == 0)
+        && line.chars().all(|c| c == '\n' || c == '\r')
+        && !cols.is_empty();
+    if empty {
+        out.push_str(&highlight);
+        out.push(' ');
+    }
+    out.push_str(&reset);
+    let used = width.clamp(cols.start, cols.end) - cols.start + usize::from(empty);
+    out.push_str(&" ".repeat(cols.len().saturating_sub(used)));
+    out
+}
+
+// Text and statusline of one window
+fn draw_window<T: Write>(
+    term: &mut Terminal<T>,
+    drawn: &mut HashMap<(u16, u16), String>,
+    buf: &Buffer,
+    win: &mut Window,
+    rect: Rect,
+    sel: Option<Selection>,
+    search: Option<&Regex>,
+) {
+    let height = (rect.height as usize).saturating_sub(1);
+
+    // Calculate last line
+    let mut last_line = win.shown_lines.0 + height;
+    if last_line >= buf.line_count {
+        last_line = buf.line_count;
+    }
+    win.shown_lines.1 = last_line;
+
+    let buff_lc = buf.line_count;
+    let buf_offset = number_width(buff_lc);
+    let text_width = rect.width.saturating_sub(buf_offset + 1);
+
+    for (row, line) in (win.shown_lines.0..(win.shown_lines.0 + height)).enumerate() {
+        let mut text = String::new();
+        if buff_lc > line {
+            // Line numbers + offset
+            text.push_str(&fg(Color::RGB(153, 153, 102)).to_string());
+            for _ in 0..(buf_offset - number_width(line + 1)) {
+                text.push(' ');
+            }
+            text.push_str(&(line + 1).to_string());
+            text.push_str(&fg(Color::Reset).to_string());
+            text.push(' ');
+
+            // Render text
+            let curr_line = buf.rope.line(line);
+            let start = buf.rope.line_to_char(line);
+            let span = sel
+                .and_then(|s| s.span(buf, line, TAB_WIDTH))
+                .map(|r| r.start - start..r.end - start);
+            let found = match search {
+                Some(re) => search::line_matches(buf, re, line),
+                None => Vec::new(),
+            };
+            let cols = win.shown_col..win.shown_col + text_width as usize;
+            text.push_str(&draw_line(&curr_line, cols, span, &found));
+        } else {
+            text.push('~');
+            for _ in 1..rect.width {
+                text.push(' ');
+            }
+        }
+
+        // Rows that look the same as last time are left alone
+        let at = (rect.x, rect.y + row as u16);
+        if drawn.get(&at) != Some(&text) {
+            term.set_cursor_to(at.0, at.1).unwrap();
+            term.print(&text).unwrap();
+            drawn.insert(at, text);
+        }
+    }
+
+    term.set_cursor_to(rect.x, rect.y + rect.height - 1)
+        .unwrap();
+    draw_statusline(term, buf, rect.width).unwrap();
+}
+
+impl Component for Editor {
+    type Widget = Editor;
+
+    // WidgetReturn
+    type WidgetReturn = ();
+
+    fn new() -> Self::Widget {
+        Editor {
+            editor: None,
+            current_line: 1,
+            current_index: 1,
+            shown_lines: (0, 1),
+            shown_col: 0,
+            desired_col: 0,
+            tabbar: TabBar::new(),
+            windows: Vec::new(),
+            layout: Layout::Window(1),
+            active_win: 1,
+            next_win: 2,
+            cmdline: String::new(),
+            prompt: ':',
+            search_start: (1, 1, 0),
+            search: None,
+            confirm: None,
+            message: None,
+            pending: Pending::default(),
+            visual: None,
+            block_insert: None,
+            recording: None,
+            last_macro: None,
+            macro_depth: 0,
+            last_change: None,
+            inserting: None,
+            asking: None,
+            last_find: None,
+            drawn: HashMap::new(),
+            drawn_size: (0, 0),
+        }
+    }
+
+    fn destroy<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        // Keep history around for the next session
+        if let Some(e) = &self.editor {
+            for buf in e.buffers.iter() {
+                let _ = buf.write_undo_file();
+            }
+        }
+        Ok(())
+    }
+
+    fn view<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        // Inital values
+        let (x, _y) = term.get_size(); // TODO: Fix this
+        let area = self.window_area(term);
+        if term.get_size() != self.drawn_size {
+            self.clear(term);
+        }
+        self.save_window();
+        // The match `:s///c` is asking about shows like a selection
+        let sel = self.selection().or_else(|| {
+            let (range, _) = self.confirm.as_ref()?.current()?;
+            let mut sel = Selection::new(Kind::Char, range.start);
+            sel.head = range.end.saturating_sub(1).max(range.start);
+            Some(sel)
+        });
+        // Matches of the search being typed
+        let search = match self.cur_buf().map(|b| b.mode) {
+            Some(Mode::Command) if self.prompt != ':' && !self.cmdline.is_empty() => {
+                search::compile(&self.cmdline).ok()
+            }
+            _ => None,
+        };
+
+        // Render Windows
+        if let Some(e) = &self.editor {
+            self.tabbar.set_tabs(e);
+            self.tabbar.view(term).unwrap();
+
+            for (id, rect) in self.layout.rects(area) {
+                let win = match self.windows.iter_mut().find(|w| w.id == id) {
+                    Some(w) => w,
+                    None => continue,
+                };
+                // The buffer was closed, show the current one instead
+                if e.get(win.buf).is_none() {
+                    if let Some(cur) = e.cur_buf {
+                        *win = Window::new(id, cur);
+                    }
+                }
+                if let Some(buf) = e.get(win.buf) {
+                    let drawn = &mut self.drawn;
+                    if id == self.active_win {
+                        draw_window(term, drawn, buf, win, rect, sel, search.as_ref());
+                    } else {
+                        draw_window(term, drawn, buf, win, rect, None, None);
+                    }
+                }
+
+                // Separator between side by side windows
+                if rect.x + rect.width <= x {
+                    for row in rect.y..rect.y + rect.height {
+                        term.set_cursor_to(rect.x + rect.width, row).unwrap();
+                        term.print("\u{2502}").unwrap();
+                    }
+                }
+
+                if id == self.active_win {
+                    self.shown_lines = win.shown_lines;
+                    self.shown_col = win.shown_col;
+                }
+            }
+        }
+        self.draw_cmdline(term);
+        self.place_cursor(term);
+        term.show_cursor().unwrap();
+
+        Ok(())
+    }
+
+    fn handle_key<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> Result<Self::WidgetReturn, Error> {
+        for key in keys.clone() {
+            if let Some((_, typed)) = &mut self.recording {
+                typed.push(key);
+            }
+            if self.process_key(term, key, keys.clone())? {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn render<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> Result<Self::WidgetReturn, Error> {
+        // Something else had the screen
+        self.clear(term);
+        self.scroll(term);
+        self.view(term).unwrap();
+
+        match self.handle_key(term, keys) {
+            Ok(s) => Ok(()),
+            Err(s) => Err(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(text: &str) -> Editor {
+        let mut e = editor::Editor::new();
+        e.add(Buffer::new().set_text(text));
+        Editor::new().set_editor(e)
+    }
+
+    #[test]
+    fn window_line_past_the_end() {
+        // :sp, G, Ctrl-w w, ggdG, Ctrl-w w
+        let mut ed = editor("one\ntwo\nthree\nfour");
+        ed.split_window(SplitDir::Horizontal);
+        ed.goto_line(4);
+        ed.current_index = 3;
+        ed.cycle_window();
+        let buf = ed.cur_buf_mut().unwrap();
+        let len = buf.rope.len_chars();
+        buf.delete(0..len);
+        ed.goto_line(1);
+        ed.cycle_window();
+
+        assert_eq!((ed.current_line, ed.current_index), (1, 1));
+        assert_eq!(ed.cursor_char(), 0);
+    }
+
+    #[test]
+    fn buffer_line_past_the_end() {
+        let mut ed = editor("one\ntwo\nthree\nfour\n");
+        let buf = ed.cur_buf_mut().unwrap();
+        buf.last_cursor = (4, 3, 2);
+        buf.delete(4..19);
+        ed.restore_cursor();
+        assert_eq!((ed.current_line, ed.current_index), (2, 1));
+        assert_eq!(ed.cursor_char(), 4);
+    }
+
+    #[test]
+    fn forced_quit_skips_saving() {
+        let dir = std::env::temp_dir().join(format!("zed-quit-{}", std::process::id()));
+        let p = dir.join("file.txt");
+        let mut e = editor::Editor::new().set_save_on_exit(true);
+        e.add(Buffer::new().set_path(p.clone()));
+        let mut ed = Editor::new().set_editor(e);
+        ed.cur_buf_mut().unwrap().insert_str(0, "text");
+
+        // The dir doesn't exist, so saving on exit fails
+        assert!(matches!(ed.quit(false), Err(Error::CouldNotSave)));
+        assert!(ed.quit(true).is_ok());
+        assert!(!p.exists());
+
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(ed.quit(false).is_ok());
+        assert_eq!(std::fs::read_to_string(&p).unwrap(), "text");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn type_keys(ed: &mut Editor, term: &mut Terminal<Vec<u8>>, text: &str) {
+        for key in keys::from_text(text) {
+            match ed.cur_buf().unwrap().mode {
+                Mode::Insert => ed.insert_mode_key(term, key).unwrap(),
+                Mode::Visual => ed.visual_key(term, key).unwrap(),
+                _ if key == Key::Char('v') => ed.enter_visual(term, Kind::Char).unwrap(),
+                _ if key == Key::Ctrl('v') => ed.enter_visual(term, Kind::Block).unwrap(),
+                _ => assert!(ed.normal_key(term, key).unwrap()),
+            }
+        }
+    }
+
+    #[test]
+    fn insert_with_count() {
+        let mut term = Terminal::new(Vec::new()).unwrap();
+        let mut ed = editor("ab\n");
+        type_keys(&mut ed, &mut term, "3ifx<BS>oo<Esc>");
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), "foofoofooab\n");
+
+        // `.` with a count of its own
+        type_keys(&mut ed, &mut term, "02.");
+        assert_eq!(
+            ed.cur_buf().unwrap().rope.to_string(),
+            "foofoofoofoofooab\n"
+        );
+        ed.cur_buf_mut().unwrap().undo();
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), "foofoofooab\n");
+
+        let mut ed = editor("a\nb");
+        type_keys(&mut ed, &mut term, "2ox<Esc>k.");
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), "a\nx\nx\nx\nx\nb");
+        type_keys(&mut ed, &mut term, "2Ay<Esc>gg3O-<Esc>");
+        assert_eq!(
+            ed.cur_buf().unwrap().rope.to_string(),
+            "-\n-\n-\na\nx\nx\nxyy\nx\nb"
+        );
+    }
+
+    #[test]
+    fn find_and_match_keys() {
+        let mut term = Terminal::new(Vec::new()).unwrap();
+        let mut ed = editor("call(a, b) 1, 2, 3\n");
+        type_keys(&mut ed, &mut term, "f1");
+        assert_eq!(ed.cursor_char(), 11);
+        type_keys(&mut ed, &mut term, "F,;");
+        assert_eq!(ed.cursor_char(), 6);
+        type_keys(&mut ed, &mut term, ",,");
+        assert_eq!(ed.cursor_char(), 15);
+        type_keys(&mut ed, &mut term, "0dt(");
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), "(a, b) 1, 2, 3\n");
+        type_keys(&mut ed, &mut term, "d%");
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), " 1, 2, 3\n");
+        // Nothing to find, nothing deleted
+        type_keys(&mut ed, &mut term, "dfx");
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), " 1, 2, 3\n");
+        type_keys(&mut ed, &mut term, "d2f,");
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), " 3\n");
+        type_keys(&mut ed, &mut term, "vt3d");
+        assert_eq!(ed.cur_buf().unwrap().rope.to_string(), "3\n");
+    }
+
+    #[test]
+    fn long_lines_scroll_sideways() {
+        let mut term = Terminal::new(Vec::new()).unwrap();
+        let text = format!("{}\nshort\n", "x".repeat(200));
+        let mut ed = editor(&text);
+        let width = ed.active_rect(&term).width as usize - number_width(2) as usize - 1;
+        type_keys(&mut ed, &mut term, "$");
+        assert_eq!(ed.shown_col, 200 - width);
+        ed.place_cursor(&mut term);
+        let rect = ed.active_rect(&term);
+        assert!(term.x_pos < rect.x + rect.width);
+        type_keys(&mut ed, &mut term, "0");
+        assert_eq!(ed.shown_col, 0);
+        type_keys(&mut ed, &mut term, "$j");
+        assert_eq!(ed.shown_col, 0);
+    }
+
+    #[test]
+    fn block_yank_and_paste() {
+        let mut term = Terminal::new(Vec::new()).unwrap();
+        let text = "abc\nd\nefg\n";
+        let mut ed = editor(text);
+        type_keys(&mut ed, &mut term, "<C-v>jjly");
+        let reg = ed.editor.as_ref().unwrap().registers.get('"').unwrap();
+        assert_eq!((reg.text.as_str(), reg.blockwise), ("ab\nd\nef", true));
+
+        // Short lines are padded out to the column
+        type_keys(&mut ed, &mut term, "$p");
+        assert_eq!(
+            ed.cur_buf().unwrap().rope.to_string(),
+            "abcab\nd  d\nefgef\n"
+        );
+        assert_eq!(ed.cursor_char(), 3);
+        ed.cur_buf_mut().unwrap().undo();
+
+        // Rows followed by text are padded to the same width
+        type_keys(&mut ed, &mut term, "gg0P");
+        assert_eq!(
+            ed.cur_buf().unwrap().rope.to_string(),
+            "ababc\nd d\nefefg\n"
+        );
+        ed.cur_buf_mut().unwrap().undo();
+
+        // Lines are added past the end
+        type_keys(&mut ed, &mut term, "gg2j2P");
+        assert_eq!(
+            ed.cur_buf().unwrap().rope.to_string(),
+            "abc\nd\nababefg\nd d\nefef\n"
+        );
+    }
+
+    // What a drawn row looks like, without colors
+    fn visible(row: &str) -> String {
+        let mut out = String::new();
+        let mut escape = false;
+        for c in row.chars() {
+            match c {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                _ if !escape => out.push(c),
+                _ => (),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn only_changed_rows_are_drawn() {
+        let mut term = Terminal::new(Vec::new()).unwrap();
+        let mut ed = editor("ab\n\tc\n");
+        ed.view(&mut term).unwrap();
+        let width = term.get_size().0 as usize;
+        let rows = ed.text_height(&term);
+        assert_eq!(ed.drawn.len(), rows);
+        // Every row covers the whole width, nothing has to be cleared
+        let row = |ed: &Editor, y: u16| visible(&ed.drawn[&(1, TEXT_TOP + y)]);
+        assert_eq!(row(&ed, 0), format!("{:<w$}", "1 ab", w = width));
+        assert_eq!(row(&ed, 1), format!("{:<w$}", "2     c", w = width));
+        assert_eq!(row(&ed, 3), format!("{:<w$}", "~", w = width));
+
+        type_keys(&mut ed, &mut term, "ix");
+        ed.view(&mut term).unwrap();
+        assert_eq!(row(&ed, 0), format!("{:<w$}", "1 xab", w = width));
+        assert_eq!(ed.drawn.len(), rows);
+
+        // A new size draws everything again
+        term.rel_size.0 = 40;
+        ed.view(&mut term).unwrap();
+        assert_eq!(row(&ed, 0), format!("{:<40}", "1 xab"));
     }
 }
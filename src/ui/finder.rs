@@ -1,171 +1,345 @@
-const MAX_CHECKS: usize = 1024;
-
+// Items shown by a picker, best first
+const MAX_RESULTS: usize = 30;
+// Live grep stops looking after this many lines
+const MAX_GREP_RESULTS: usize = 30;
+// Widest result, the results take the first 60 columns of the popup
+const RESULT_WIDTH: usize = 56;
+// Narrower terminals get no preview
+const MIN_PREVIEW_COLS: u16 = 100;
+// Widest the popup gets with a preview
+const MAX_POPUP_WIDTH: u16 = 140;
+// Bigger files are summarized instead of shown
+const MAX_PREVIEW_BYTES: u64 = 1024 * 1024;
+// Like git, a NUL in the first bytes means a binary file
+const BINARY_CHECK_BYTES: usize = 8000;
+// How often a picker waiting for a key looks for changed items
+const REDRAW_EVERY: Duration = Duration::from_millis(100);
+// Live grep waits this long after the last key before searching
+const GREP_DELAY: Duration = Duration::from_millis(150);
+
+use crate::backend::search;
 use crate::cli::Target;
 // Generic Finder
 use crate::error::Error;
 use crate::ui::Component;
+use crate::utils::{fuzzy, index};
+use regex::Regex;
 use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::fmt::{self, Display, Error as FmtError};
-use std::io;
+use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use zui_core::color::{self, fg, Color};
 use zui_core::key::{Key, KeyIterator};
 use zui_core::term::Terminal;
 use zui_core::widgets::popup::Popup;
 use zui_core::widgets::Widget;
 
-// Generic fuzzy finder
-// TODO: Make more generic
-fn finder<T: Write, W: Display>(term: &mut Terminal<T>, r: &[W]) {
-    // Cur possition before doing shit
-    let curr_pos = term.get_cursor().unwrap();
-    term.hide_cursor().unwrap();
+// What a Picker picks from
+// The picker owns the popup, moving around and fuzzy matching,
+// a source only says what the items are and what picking one does.
+pub trait Source {
+    type Item;
+    // What picking an item gives back
+    type Output;
+
+    fn title(&self) -> String;
 
-    let p = Popup::new(term).title("").width(60).height(25);
-    let p_deets = p.render(term).unwrap();
+    // Items for the search. Unless the source is `live`, the picker
+    // fuzzy matches the search against their `text` itself.
+    fn items(&mut self, search: &str) -> Vec<Self::Item>;
 
-    term.set_cursor_to(p_deets.starting_pos.0 + 2, p_deets.ending_pos.1 - 1)
-        .unwrap();
+    // What is shown and matched
+    fn text(&self, item: &Self::Item) -> String;
 
-    let mut max_val = (p.height - 1) as usize;
+    fn icon(&self, _item: &Self::Item) -> &'static str {
+        ""
+    }
+
+    // Items changed by themselves since `items`, like files the
+    // indexer found in the meantime
+    fn changed(&mut self) -> bool {
+        false
+    }
 
-    if max_val > r.len() {
-        max_val = r.len();
+    // Items already match the search, like grep results
+    fn live(&self) -> bool {
+        false
     }
 
-    for l in &r[..max_val] {
-        term.print(l).unwrap();
-        term.set_cursor_to(term.x_pos, term.y_pos - 1).unwrap();
+    // File and 1-based line to show for the item, 0 for the whole file
+    fn preview(&self, _item: &Self::Item) -> Option<(PathBuf, usize)> {
+        None
     }
 
-    term.set_cursor_to(curr_pos.0, curr_pos.1).unwrap();
-    term.show_cursor().unwrap()
+    fn accept(&self, item: &Self::Item) -> Self::Output;
+
+    // Keys the source handles itself, true when the items changed
+    fn key(&mut self, _key: Key) -> bool {
+        false
+    }
 }
 
-pub struct FileFinder {
-    results: Vec<FileResult>,
+// Generic fuzzy finder over a Source
+// Results go up from the bottom of the popup, the search is below them
+pub struct Picker<S: Source> {
+    source: S,
+    // Items shown and the chars of their text matched by the search
+    results: Vec<(S::Item, Vec<usize>)>,
     currently_selected_index: Option<usize>,
     search: String,
-    dir: PathBuf,
+    // Results drawn, the popup may have room for fewer than there are
+    shown: usize,
 }
 
-pub struct FileResult {
-    path: PathBuf,
-    icon: &'static str,
-}
+impl<S: Source> Picker<S> {
+    pub fn set_source(mut self, source: S) -> Picker<S> {
+        self.source = source;
+        self.refresh();
+        self
+    }
 
-impl FileResult {
-    pub fn new() -> FileResult {
-        FileResult {
-            path: PathBuf::new(),
-            icon: "",
+    // Match the items against the search, best first
+    fn refresh(&mut self) {
+        self.currently_selected_index = None;
+        let items = self.source.items(&self.search);
+        if self.source.live() {
+            self.results = items
+                .into_iter()
+                .take(MAX_RESULTS)
+                .map(|i| (i, Vec::new()))
+                .collect();
+            return;
+        }
+        // Nothing to rank without a search, keep the source's order
+        if self.search.is_empty() {
+            self.results = items
+                .into_iter()
+                .take(MAX_RESULTS)
+                .map(|i| (i, Vec::new()))
+                .collect();
+            return;
         }
-    }
-}
 
-impl Display for FileResult {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.path.to_str() {
-            Some(x) => write!(f, "{}  {}", self.icon, x),
-            None => Err(FmtError),
+        let mut scored = Vec::new();
+        let mut matcher = fuzzy::Matcher::new(&self.search);
+        for item in items {
+            let text = self.source.text(&item);
+            if let Some(m) = matcher.score(&text) {
+                scored.push((m.score, text.chars().count(), item, m.positions));
+            }
         }
+        // Shorter texts win ties
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        self.results = scored
+            .into_iter()
+            .take(MAX_RESULTS)
+            .map(|(_, _, i, p)| (i, p))
+            .collect();
     }
-}
 
-impl FileFinder {
-    pub fn set_dir(mut self, dir: PathBuf) -> FileFinder {
-        self.dir = dir.to_path_buf();
-        self
+    // A result as shown, matched chars are highlighted
+    fn line(&self, i: usize) -> String {
+        let (item, positions) = &self.results[i];
+        let icon = self.source.icon(item);
+        let mut line = String::new();
+        let mut width = 0;
+        if !icon.is_empty() {
+            line.push_str(icon);
+            line.push_str("  ");
+            width = 3;
+        }
+        for (i, c) in self.source.text(item).chars().enumerate() {
+            if width >= RESULT_WIDTH {
+                break;
+            }
+            width += 1;
+            if positions.contains(&i) {
+                line.push_str(&format!(
+                    "{}{}{}",
+                    fg(Color::YellowLight),
+                    c,
+                    fg(Color::Reset)
+                ));
+            } else {
+                line.push(c);
+            }
+        }
+        while width < RESULT_WIDTH {
+            line.push(' ');
+            width += 1;
+        }
+        line
     }
 
-    fn search_dir(&self, p: &PathBuf) -> io::Result<Vec<FileResult>> {
-        let mut dirs_list = Vec::new();
-        let mut checked_files: usize = 0;
-        if p.is_dir() {
-            for entry in WalkDir::new(p)
-                .min_depth(1)
-                .into_iter()
-                .filter_map(|e| e.ok())
-            {
-                let path = entry.path().to_owned();
-                if path.to_string_lossy().contains(".git/")
-                    || path.to_string_lossy().contains("target/debug/")
-                    || path.to_string_lossy().contains("target/release/")
-                {
-                    continue;
+    // Returns what the picker gives back once a key closes it
+    fn picker_key<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        key: Key,
+    ) -> Option<Option<S::Output>> {
+        match key {
+            Key::Esc => {
+                term.clear_screen().unwrap();
+                return Some(None);
+            }
+            // Takes the first result when none is selected
+            Key::Enter => match self.current() {
+                Some(item) => return Some(Some(self.source.accept(item))),
+                None => return None,
+            },
+            // Results go up from the search line
+            Key::Up => {
+                if self.shown == 0 {
+                    return None;
                 }
-                checked_files += 1;
-
-                if dirs_list.len() <= 30 {
-                    if path.is_dir() {
-                        continue;
-                    }
-                    if let Some(x) = path.to_str() {
-                        if x.to_owned().contains(&self.search) {
-                            dirs_list.push(FileResult {
-                                path: path.to_path_buf(),
-                                icon: derive_file_type(&path),
-                            });
-                        } else {
-                            continue;
-                        }
-                    }
-                } else {
-                    return Ok(dirs_list);
+                self.currently_selected_index = match self.currently_selected_index {
+                    Some(p) => Some((p + 1).min(self.shown - 1)),
+                    None => Some(0),
+                };
+            }
+            Key::Down => {
+                self.currently_selected_index = match self.currently_selected_index {
+                    Some(0) | None => None,
+                    Some(p) => Some(p - 1),
+                };
+            }
+            Key::Char(x) => {
+                self.search.push(x);
+                self.refresh();
+            }
+            Key::Backspace => {
+                self.search.pop();
+                self.refresh();
+            }
+            k => {
+                if !self.source.key(k) {
+                    return None;
                 }
+                self.refresh();
             }
         }
-
-        Ok(dirs_list)
+        self.draw(term);
+        None
     }
-}
 
-fn derive_file_type(p: &Path) -> &'static str {
-    let mut file_map: HashMap<&OsStr, &str> = HashMap::new();
-    file_map.insert(OsStr::new("rs"), "\u{e7a8}");
-    file_map.insert(OsStr::new("md"), "\u{e73e}");
-    file_map.insert(OsStr::new("py"), "\u{e73c}");
-    file_map.insert(OsStr::new("asm"), "\u{e796}");
-    file_map.insert(OsStr::new("c"), "\u{e61e}");
-    file_map.insert(OsStr::new("cpp"), "\u{e61d}");
-    file_map.insert(OsStr::new("h"), "\u{e61e}");
-    file_map.insert(OsStr::new("html"), "\u{e736}");
-    file_map.insert(OsStr::new("css"), "\u{e749}");
-    file_map.insert(OsStr::new("go"), "\u{e626}");
-    file_map.insert(OsStr::new("lua"), "\u{e620}");
-    file_map.insert(OsStr::new("php"), "\u{e73d}");
-    file_map.insert(OsStr::new("pl"), "\u{e769}");
-    file_map.insert(OsStr::new("js"), "\u{e718}");
-    file_map.insert(OsStr::new("java"), "\u{e718}");
-    file_map.insert(OsStr::new("json"), "\u{fb25}");
-    file_map.insert(OsStr::new("cs"), "\u{f81a}");
+    // Everything is drawn over what was there, clearing the screen
+    // on every key makes it flash
+    fn draw<T: Write>(&mut self, term: &mut Terminal<T>) {
+        let (cols, _) = term.get_size();
+        let with_preview = cols >= MIN_PREVIEW_COLS;
+        let width = if with_preview {
+            (cols - 4).min(MAX_POPUP_WIDTH)
+        } else {
+            60
+        };
+        let x = Popup::new(term)
+            .title(&self.source.title())
+            .width(width)
+            .height(2)
+            .y_offset(14);
+        let x_deets = x.render(term).unwrap();
+        let input = (x_deets.starting_pos.0 + 2, x_deets.starting_pos.1 + 1);
+
+        // Where the highlighted item is, like `main.rs:12`
+        let target = self.current().and_then(|i| self.source.preview(i));
+        let place = match &target {
+            Some((path, line)) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if *line > 0 {
+                    format!("{}:{}", name, line)
+                } else {
+                    name.to_string()
+                }
+            }
+            None => String::new(),
+        };
+        term.hide_cursor().unwrap();
+        let p = Popup::new(term).title(&place).width(width).height(25);
+        let p_deets = p.render(term).unwrap();
+        let first = (p_deets.starting_pos.0 + 2, p_deets.ending_pos.1 - 1);
+        // Rows between the top border and the first result
+        let rows = first.1.saturating_sub(p_deets.starting_pos.1) as usize;
+        let shown = self.results.len().min(rows);
+        self.shown = shown;
+        self.currently_selected_index = self
+            .currently_selected_index
+            .filter(|_| shown > 0)
+            .map(|i| i.min(shown - 1));
+        for i in 0..rows {
+            term.set_cursor_to(first.0, first.1 - i as u16).unwrap();
+            if i < shown {
+                term.print(self.line(i)).unwrap();
+            } else {
+                term.print(" ".repeat(RESULT_WIDTH)).unwrap();
+            }
+        }
 
-    let ext = p.extension();
+        // The preview is right of the results, past a separator
+        if with_preview {
+            let top = p_deets.starting_pos.1 + 1;
+            let height = p_deets.ending_pos.1.saturating_sub(top);
+            let sep = p_deets.starting_pos.0 + 59;
+            for row in top..top + height {
+                term.set_cursor_to(sep, row).unwrap();
+                term.print("\u{2502}").unwrap();
+            }
+            let pane = Rect {
+                x: sep + 2,
+                y: top,
+                width: p_deets.ending_pos.0.saturating_sub(sep + 3) as usize,
+                height: height as usize,
+            };
+            let shown = match &target {
+                Some((path, line)) => preview(path, *line, pane.height),
+                None => Preview::Summary(String::new()),
+            };
+            draw_preview(term, pane, &shown);
+        }
+        term.show_cursor().unwrap();
 
-    if let Some(x) = ext {
-        match file_map.get(x) {
-            Some(p) => p,
-            None => "\u{f15c}",
+        term.set_cursor_to(input.0, input.1).unwrap();
+        let input_width = x_deets.ending_pos.0.saturating_sub(input.0) as usize;
+        let mut search = self.search.clone();
+        while search.chars().count() < input_width {
+            search.push(' ');
         }
-    } else {
-        "\u{f15c}"
+        term.print(search).unwrap();
+        // The cursor sits on the selected result
+        match self.currently_selected_index {
+            Some(i) => term.set_cursor_to(first.0, first.1 - i as u16).unwrap(),
+            None => {
+                let x = input.0 + self.search.chars().count() as u16;
+                term.set_cursor_to(x, input.1).unwrap()
+            }
+        }
+    }
+
+    // The highlighted item, or the first one when none is
+    fn current(&self) -> Option<&S::Item> {
+        let i = self.currently_selected_index.unwrap_or(0);
+        self.results.get(i).map(|(item, _)| item)
     }
 }
 
-impl Component for FileFinder {
-    type Widget = FileFinder;
+impl<S: Source + Default> Component for Picker<S> {
+    type Widget = Picker<S>;
 
-    // WidgetReturn
-    type WidgetReturn = Target;
+    // Nothing when closed with Esc
+    type WidgetReturn = Option<S::Output>;
 
     fn new() -> Self::Widget {
-        FileFinder {
-            results: vec![FileResult::new()],
-            dir: PathBuf::new(),
-            search: String::new(),
+        Picker {
+            source: S::default(),
+            results: Vec::new(),
             currently_selected_index: None,
+            search: String::new(),
+            shown: 0,
         }
     }
 
@@ -176,120 +350,601 @@ impl Component for FileFinder {
         }
     }
 
-    // Fix all these unwraps
     fn view<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
-        // Inital values
-        let x = Popup::new(term)
-            .title("Find")
-            .width(60)
-            .height(2)
-            .y_offset(14);
-
-        let x_deets = x.render(term).unwrap();
-
-        term.set_cursor_to(x_deets.starting_pos.0 + 2, x_deets.starting_pos.1 + 1)
-            .unwrap();
-
-        self.results = self.search_dir(&self.dir).unwrap();
-        finder(term, &self.results[..]);
+        self.draw(term);
         Ok(())
     }
 
+    // Keys are read on another thread so the picker can redraw while
+    // waiting, when the source changes. Only one key is asked for at a
+    // time, so none are read past the one that closes the picker.
     fn handle_key<T: Write>(
         &mut self,
         term: &mut Terminal<T>,
         keys: KeyIterator,
     ) -> Result<Self::WidgetReturn, Error> {
-        for key in keys.clone() {
-            match key {
-                Key::Esc => {
-                    self.destroy(term).unwrap();
-                    return Ok(Target::Empty);
+        let (ask, asked) = mpsc::channel::<()>();
+        let (send, read) = mpsc::channel();
+        let mut reader = keys.clone();
+        thread::spawn(move || {
+            for _ in asked.iter() {
+                if send.send(reader.next()).is_err() {
+                    break;
                 }
-                Key::Enter => match self.currently_selected_index {
-                    Some(p) => {
-                        return Ok(Target::File(self.results[p].path.clone()));
-                    }
-                    None => continue,
-                },
-                Key::Up => match self.currently_selected_index {
-                    Some(p) => {
-                        if p <= self.results.len() - 2 {
-                            self.currently_selected_index = Some(p + 1);
-                            term.set_cursor_to(term.x_pos, term.y_pos - 1).unwrap();
-                        }
-                    }
-                    None => {
-                        self.currently_selected_index = Some(0);
-                        let go_back = self.search.chars().count();
-                        term.set_cursor_to(term.x_pos - go_back as u16 + 3, term.y_pos - 3)
-                            .unwrap();
-                    }
-                },
-                Key::Down => match self.currently_selected_index {
-                    Some(p) => {
-                        if p == 0 {
-                            let go_back = self.search.chars().count();
-                            term.set_cursor_to(term.x_pos + go_back as u16 - 3, term.y_pos + 3)
-                                .unwrap();
-                            self.currently_selected_index = None;
-                        } else {
-                            self.currently_selected_index = Some(p - 1);
-                            term.set_cursor_to(term.x_pos, term.y_pos + 1).unwrap();
+            }
+        });
+
+        loop {
+            let _ = ask.send(());
+            let key = loop {
+                match read.recv_timeout(REDRAW_EVERY) {
+                    Ok(key) => break key,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if self.source.changed() {
+                            let selected = self.currently_selected_index;
+                            self.refresh();
+                            self.currently_selected_index = selected;
+                            self.view(term).unwrap();
                         }
                     }
-                    None => {
-                        continue;
-                    }
-                },
-                Key::Char(x) => {
-                    self.search.push(x);
-
-                    term.set_cursor_to(term.x_pos - 2, term.y_pos - 2).unwrap();
-                    term.clear_above_cursor().unwrap();
-                    term.set_cursor_to(term.x_pos + 2, term.y_pos + 2).unwrap();
-
-                    self.results = self.search_dir(&self.dir).unwrap();
-                    finder(term, &self.results[..]);
-
-                    term.print(x.to_string().as_str()).unwrap();
-                    term.set_cursor_to(term.x_pos + 1, term.y_pos).unwrap();
+                    Err(RecvTimeoutError::Disconnected) => break None,
                 }
-                Key::Backspace => {
-                    if self.search.len() >= 1 {
-                        self.search = self.search[..self.search.len() - 1].to_string();
-
-                        term.set_cursor_to(term.x_pos - 2, term.y_pos - 2).unwrap();
-                        term.clear_above_cursor().unwrap();
-                        term.set_cursor_to(term.x_pos + 2, term.y_pos + 2).unwrap();
-
-                        self.results = self.search_dir(&self.dir).unwrap();
-                        finder(term, &self.results[..]);
-
-                        term.set_cursor_to(term.x_pos - 1, term.y_pos).unwrap();
-                        term.print(" ").unwrap();
-                        term.set_cursor_to(term.x_pos, term.y_pos).unwrap();
+            };
+            match key {
+                Some(key) => {
+                    if let Some(out) = self.picker_key(term, key) {
+                        return Ok(out);
                     }
-                } //TODO: Add Arrow Keys
-                _ => continue,
+                }
+                None => return Ok(None),
             }
         }
-        Ok(Target::Empty)
     }
 
+    // Clears the screen once, later views draw over the popup
     fn render<T: Write>(
         &mut self,
         term: &mut Terminal<T>,
         keys: KeyIterator,
     ) -> Result<Self::WidgetReturn, Error> {
+        term.clear_screen().unwrap();
         self.view(term).unwrap();
+        self.handle_key(term, keys)
+    }
+}
 
-        match self.handle_key(term, keys) {
-            Ok(s) => match s {
-                Target::File(x) => return Ok(Target::File(x)),
-                _ => return Ok(Target::Empty),
+// Files under `dir`, from the project index
+#[derive(Default)]
+pub struct FileSource {
+    dir: PathBuf,
+    // Show hidden and ignored files too, toggled with Ctrl-a
+    all: bool,
+    // Version of the index the items came from
+    seen: u64,
+}
+
+impl FileSource {
+    pub fn set_dir(mut self, dir: PathBuf) -> FileSource {
+        self.dir = dir;
+        self
+    }
+}
+
+impl Source for FileSource {
+    type Item = PathBuf;
+    type Output = Target;
+
+    fn title(&self) -> String {
+        let mut title = String::from(if self.all { "Find (all files)" } else { "Find" });
+        if index::get(&self.dir, self.all).files().indexing {
+            title.push_str(" - indexing...");
+        }
+        title
+    }
+
+    // Only what the indexer found so far while it is still going
+    fn items(&mut self, _search: &str) -> Vec<PathBuf> {
+        if !self.dir.is_dir() {
+            return Vec::new();
+        }
+        let index = index::get(&self.dir, self.all);
+        let files = index.files();
+        self.seen = files.version;
+        files.paths.iter().cloned().collect()
+    }
+
+    fn changed(&mut self) -> bool {
+        index::get(&self.dir, self.all).files().version != self.seen
+    }
+
+    // Path relative to the dir
+    fn text(&self, item: &PathBuf) -> String {
+        let name = item.strip_prefix(&self.dir).unwrap_or(item);
+        name.to_string_lossy().to_string()
+    }
+
+    fn icon(&self, item: &PathBuf) -> &'static str {
+        derive_file_type(item)
+    }
+
+    fn preview(&self, item: &PathBuf) -> Option<(PathBuf, usize)> {
+        Some((item.clone(), 0))
+    }
+
+    fn accept(&self, item: &PathBuf) -> Target {
+        Target::File(item.clone())
+    }
+
+    fn key(&mut self, key: Key) -> bool {
+        match key {
+            // Show hidden and ignored files too, or hide them again
+            Key::Ctrl('a') => {
+                self.all = !self.all;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Live grep, searches the contents of every file under `dir`
+// The search runs on another thread once no key was typed for a moment,
+// a new search stops the one still running
+#[derive(Default)]
+pub struct GrepSource {
+    dir: PathBuf,
+    search: String,
+    // When the search was typed, until it starts
+    typed: Option<Instant>,
+    // Results so far of the running search, and whether it is done
+    found: Option<Receiver<(Vec<GrepResult>, bool)>>,
+    // Set to stop the running search
+    cancel: Arc<AtomicBool>,
+    results: Vec<GrepResult>,
+}
+
+#[derive(Clone)]
+pub struct GrepResult {
+    path: PathBuf,
+    // Path relative to the searched dir
+    name: String,
+    // 1-based
+    line: usize,
+    text: String,
+}
+
+impl GrepSource {
+    pub fn set_dir(mut self, dir: PathBuf) -> GrepSource {
+        self.dir = dir;
+        self
+    }
+
+    fn start(&mut self) {
+        // As a regex when it is a valid one
+        let re = match search::compile(&self.search) {
+            Ok(re) => re,
+            Err(_) => match search::compile(&regex::escape(&self.search)) {
+                Ok(re) => re,
+                Err(_) => return,
             },
-            Err(s) => Err(s),
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (send, found) = mpsc::channel();
+        self.cancel = cancel.clone();
+        self.found = Some(found);
+        let dir = self.dir.clone();
+        thread::spawn(move || grep(&dir, &re, &cancel, &send));
+    }
+
+    fn stop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.found = None;
+        self.typed = None;
+    }
+}
+
+impl Drop for GrepSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Lines of the files under `dir` matching `re`, sent every time a file
+// adds some until `cancel` is set
+fn grep(dir: &Path, re: &Regex, cancel: &AtomicBool, send: &Sender<(Vec<GrepResult>, bool)>) {
+    let mut results = Vec::new();
+    for path in index::walk(dir, false) {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        if !path.is_file() {
+            continue;
+        }
+        let text = match fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        // Binary files
+        if text.contains('\0') {
+            continue;
+        }
+        let name = path.strip_prefix(dir).unwrap_or(&path);
+        let name = name.to_string_lossy().to_string();
+        let before = results.len();
+        for (i, line) in text.lines().enumerate() {
+            if results.len() >= MAX_GREP_RESULTS {
+                break;
+            }
+            if !re.is_match(line) {
+                continue;
+            }
+            results.push(GrepResult {
+                path: path.clone(),
+                name: name.clone(),
+                line: i + 1,
+                text: line.to_string(),
+            });
+        }
+        if results.len() >= MAX_GREP_RESULTS {
+            break;
+        }
+        if results.len() > before && send.send((results.clone(), false)).is_err() {
+            return;
+        }
+    }
+    let _ = send.send((results, true));
+}
+
+impl Source for GrepSource {
+    type Item = GrepResult;
+    type Output = Target;
+
+    fn title(&self) -> String {
+        if self.typed.is_some() || self.found.is_some() {
+            String::from("Live Grep - searching...")
+        } else {
+            String::from("Live Grep")
+        }
+    }
+
+    // Results of the last search done, a new search starts once the
+    // picker sees no key for GREP_DELAY
+    fn items(&mut self, search: &str) -> Vec<GrepResult> {
+        if search != self.search {
+            self.stop();
+            self.search = search.to_string();
+            self.results.clear();
+            if !search.is_empty() {
+                self.typed = Some(Instant::now());
+            }
+        }
+        self.results.clone()
+    }
+
+    fn changed(&mut self) -> bool {
+        let mut changed = false;
+        if let Some(typed) = self.typed {
+            if typed.elapsed() < GREP_DELAY {
+                return false;
+            }
+            self.typed = None;
+            self.start();
+            // A search that never started, the title changes back
+            changed = self.found.is_none();
+        }
+
+        let found = match &self.found {
+            Some(f) => f,
+            None => return changed,
+        };
+        loop {
+            match found.try_recv() {
+                Ok((results, done)) => {
+                    self.results = results;
+                    changed = true;
+                    if done {
+                        self.found = None;
+                        break;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.found = None;
+                    changed = true;
+                    break;
+                }
+            }
+        }
+        changed
+    }
+
+    fn text(&self, item: &GrepResult) -> String {
+        format!("{}:{}: {}", item.name, item.line, item.text.trim())
+    }
+
+    fn live(&self) -> bool {
+        true
+    }
+
+    fn preview(&self, item: &GrepResult) -> Option<(PathBuf, usize)> {
+        Some((item.path.clone(), item.line))
+    }
+
+    fn accept(&self, item: &GrepResult) -> Target {
+        Target::Line(item.path.clone(), item.line)
+    }
+}
+
+// Open buffers, picking one gives its id
+#[derive(Default)]
+pub struct BufferSource {
+    // Id, name and whether it is modified, like `Editor::list`
+    buffers: Vec<(usize, String, bool)>,
+}
+
+impl BufferSource {
+    pub fn set_buffers(mut self, buffers: Vec<(usize, String, bool)>) -> BufferSource {
+        self.buffers = buffers;
+        self
+    }
+}
+
+impl Source for BufferSource {
+    type Item = (usize, String, bool);
+    type Output = usize;
+
+    fn title(&self) -> String {
+        String::from("Buffers")
+    }
+
+    fn items(&mut self, _search: &str) -> Vec<Self::Item> {
+        self.buffers.clone()
+    }
+
+    fn text(&self, item: &Self::Item) -> String {
+        item.1.clone()
+    }
+
+    fn icon(&self, item: &Self::Item) -> &'static str {
+        if item.2 {
+            "+"
+        } else {
+            " "
+        }
+    }
+
+    fn accept(&self, item: &Self::Item) -> usize {
+        item.0
+    }
+}
+
+// Ex commands, picking one gives its name
+#[derive(Default)]
+pub struct CommandSource {
+    // Name and what it does
+    commands: Vec<(&'static str, &'static str)>,
+}
+
+impl CommandSource {
+    pub fn set_commands(mut self, commands: &[(&'static str, &'static str)]) -> CommandSource {
+        self.commands = commands.to_vec();
+        self
+    }
+}
+
+impl Source for CommandSource {
+    type Item = (&'static str, &'static str);
+    type Output = &'static str;
+
+    fn title(&self) -> String {
+        String::from("Commands")
+    }
+
+    fn items(&mut self, _search: &str) -> Vec<Self::Item> {
+        self.commands.clone()
+    }
+
+    fn text(&self, item: &Self::Item) -> String {
+        format!("{:<12}{}", item.0, item.1)
+    }
+
+    fn accept(&self, item: &Self::Item) -> &'static str {
+        item.0
+    }
+}
+
+// Where the preview goes
+#[derive(Clone, Copy)]
+struct Rect {
+    x: u16,
+    y: u16,
+    width: usize,
+    height: usize,
+}
+
+// What the preview pane shows
+enum Preview {
+    // Lines starting at 1-based `first`, `line` is highlighted
+    Text {
+        lines: Vec<String>,
+        first: usize,
+        line: usize,
+    },
+    // Said instead of showing the file
+    Summary(String),
+}
+
+// `height` lines of `path` around `line`, 0 for the top of the file
+fn preview(path: &Path, line: usize, height: usize) -> Preview {
+    let meta = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(e) => return Preview::Summary(format!("Can't read file: {}", e)),
+    };
+    if meta.len() > MAX_PREVIEW_BYTES {
+        return Preview::Summary(format!("Large file, {}", size(meta.len())));
+    }
+    let bytes = match fs::read(path) {
+        Ok(b) => b,
+        Err(e) => return Preview::Summary(format!("Can't read file: {}", e)),
+    };
+    if bytes.iter().take(BINARY_CHECK_BYTES).any(|b| *b == 0) {
+        return Preview::Summary(format!("Binary file, {}", size(meta.len())));
+    }
+
+    // The line sits a third of the way down
+    let first = line.saturating_sub(height / 3).max(1);
+    let lines = String::from_utf8_lossy(&bytes)
+        .lines()
+        .skip(first - 1)
+        .take(height)
+        .map(|l| l.to_string())
+        .collect();
+    Preview::Text { lines, first, line }
+}
+
+// Like `12.3 KB`
+fn size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut n = bytes as f64;
+    let mut unit = 0;
+    while n >= 1024.0 && unit < units.len() - 1 {
+        n /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", n, units[unit])
+    }
+}
+
+// Fills the whole pane, so whatever was there before is gone
+fn draw_preview<T: Write>(term: &mut Terminal<T>, pane: Rect, preview: &Preview) {
+    let dim = format!("{}", fg(Color::RGB(153, 153, 102)));
+    let pad = |text: String, width: usize| {
+        let mut text: String = text.chars().take(width).collect();
+        while text.chars().count() < width {
+            text.push(' ');
+        }
+        text
+    };
+
+    let (lines, first, line) = match preview {
+        Preview::Text { lines, first, line } => (&lines[..], *first, *line),
+        Preview::Summary(s) => {
+            term.set_cursor_to(pane.x, pane.y).unwrap();
+            term.print(&dim).unwrap();
+            term.print(pad(s.clone(), pane.width)).unwrap();
+            term.print(fg(Color::Reset)).unwrap();
+            (&[][..], 1, 0)
         }
+    };
+    let skip = if lines.is_empty() { 1 } else { 0 };
+
+    let number_width = (first + lines.len()).to_string().len();
+    let text_width = pane.width.saturating_sub(number_width + 1);
+    for row in skip..pane.height {
+        term.set_cursor_to(pane.x, pane.y + row as u16).unwrap();
+        let l = match lines.get(row) {
+            Some(l) => l,
+            None => {
+                term.print(" ".repeat(pane.width)).unwrap();
+                continue;
+            }
+        };
+        let n = first + row;
+        term.print(&dim).unwrap();
+        term.print(format!("{:>w$} ", n, w = number_width)).unwrap();
+        term.print(fg(Color::Reset)).unwrap();
+
+        let text = pad(l.replace('\t', "    "), text_width);
+        if n == line {
+            term.print(color::bg(Color::RGB(61, 61, 41))).unwrap();
+            term.print(text).unwrap();
+            term.print(color::bg(Color::Reset)).unwrap();
+        } else {
+            term.print(text).unwrap();
+        }
+    }
+}
+
+fn derive_file_type(p: &Path) -> &'static str {
+    let mut file_map: HashMap<&OsStr, &str> = HashMap::new();
+    file_map.insert(OsStr::new("rs"), "\u{e7a8}");
+    file_map.insert(OsStr::new("md"), "\u{e73e}");
+    file_map.insert(OsStr::new("py"), "\u{e73c}");
+    file_map.insert(OsStr::new("asm"), "\u{e796}");
+    file_map.insert(OsStr::new("c"), "\u{e61e}");
+    file_map.insert(OsStr::new("cpp"), "\u{e61d}");
+    file_map.insert(OsStr::new("h"), "\u{e61e}");
+    file_map.insert(OsStr::new("html"), "\u{e736}");
+    file_map.insert(OsStr::new("css"), "\u{e749}");
+    file_map.insert(OsStr::new("go"), "\u{e626}");
+    file_map.insert(OsStr::new("lua"), "\u{e620}");
+    file_map.insert(OsStr::new("php"), "\u{e73d}");
+    file_map.insert(OsStr::new("pl"), "\u{e769}");
+    file_map.insert(OsStr::new("js"), "\u{e718}");
+    file_map.insert(OsStr::new("java"), "\u{e718}");
+    file_map.insert(OsStr::new("json"), "\u{fb25}");
+    file_map.insert(OsStr::new("cs"), "\u{f81a}");
+
+    let ext = p.extension();
+
+    if let Some(x) = ext {
+        match file_map.get(x) {
+            Some(p) => p,
+            None => "\u{f15c}",
+        }
+    } else {
+        "\u{f15c}"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Wait for the search to start and finish
+    fn wait(source: &mut GrepSource) {
+        for _ in 0..100 {
+            source.changed();
+            if source.typed.is_none() && source.found.is_none() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("grep never finished");
+    }
+
+    #[test]
+    fn grep_in_the_background() {
+        let dir = std::env::temp_dir().join(format!("zed-grep-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "one\nneedle here\ntwo\n").unwrap();
+        fs::write(dir.join("b.txt"), "no match\n").unwrap();
+        let mut source = GrepSource::default().set_dir(dir.clone());
+
+        // Nothing until no key was typed for a while
+        assert!(source.items("needle").is_empty());
+        assert!(!source.changed());
+        assert_eq!(source.title(), "Live Grep - searching...");
+        wait(&mut source);
+        let found: Vec<_> = source
+            .items("needle")
+            .iter()
+            .map(|r| source.text(r))
+            .collect();
+        assert_eq!(found, vec!["a.txt:2: needle here"]);
+        assert_eq!(source.title(), "Live Grep");
+
+        // A new search drops the old results, the same one keeps them
+        assert!(source.items("no m").is_empty());
+        wait(&mut source);
+        assert_eq!(source.items("no m").len(), 1);
+        assert_eq!(source.items("no m").len(), 1);
+        assert!(source.items("").is_empty());
+        assert!(!source.changed());
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
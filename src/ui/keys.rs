@@ -0,0 +1,130 @@
+// Keys written as text, like vim's `<Esc>` and `<C-w>`
+// Macros are kept in registers this way, so they can be pasted,
+// edited and yanked back
+use zui_core::key::Key;
+
+const NAMED: [(&str, Key); 9] = [
+    ("lt", Key::Char('<')),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("CR", Key::Enter),
+    ("Tab", Key::Tab),
+    ("Esc", Key::Esc),
+    ("BS", Key::Backspace),
+];
+
+pub fn to_text(keys: &[Key]) -> String {
+    let mut out = String::new();
+    for key in keys {
+        if let Some((name, _)) = NAMED.iter().find(|(_, k)| k == key) {
+            out.push_str(&format!("<{}>", name));
+            continue;
+        }
+        match key {
+            Key::Char(c) => out.push(*c),
+            Key::Ctrl(c) => out.push_str(&format!("<C-{}>", c)),
+            _ => (),
+        }
+    }
+    out
+}
+
+pub fn from_text(text: &str) -> Vec<Key> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+
+        if c == '<' {
+            if let Some(len) = chars[i..].iter().position(|c| *c == '>') {
+                let name: String = chars[i..i + len].iter().collect();
+                if let Some(key) = named_key(&name) {
+                    keys.push(key);
+                    i += len + 1;
+                    continue;
+                }
+            }
+        }
+        keys.push(match c {
+            '\n' => Key::Enter,
+            '\t' => Key::Tab,
+            _ => Key::Char(c),
+        });
+    }
+    keys
+}
+
+// The part between `<` and `>`
+fn named_key(name: &str) -> Option<Key> {
+    if let Some((_, key)) = NAMED.iter().find(|(n, _)| *n == name) {
+        return Some(*key);
+    }
+    let mut chars = name.strip_prefix("C-")?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(Key::Ctrl(c)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compared with `==` and shown as text, `Key` doesn't need to be Debug
+    fn check(text: &str, keys: &[Key]) {
+        let parsed = from_text(text);
+        assert!(
+            parsed == keys,
+            "{:?} parsed as {:?}",
+            text,
+            to_text(&parsed)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let keys = [
+            Key::Char('d'),
+            Key::Char('w'),
+            Key::Esc,
+            Key::Char('<'),
+            Key::Char('>'),
+            Key::Ctrl('w'),
+            Key::Char('l'),
+            Key::Up,
+            Key::Down,
+            Key::Left,
+            Key::Right,
+            Key::Enter,
+            Key::Tab,
+            Key::Backspace,
+            Key::Char(' '),
+            Key::Char('é'),
+        ];
+        let text = to_text(&keys);
+        assert_eq!(
+            text,
+            "dw<Esc><lt>><C-w>l<Up><Down><Left><Right><CR><Tab><BS> é"
+        );
+        check(&text, &keys);
+    }
+
+    #[test]
+    fn plain_text() {
+        check(
+            "ab\n\t",
+            &[Key::Char('a'), Key::Char('b'), Key::Enter, Key::Tab],
+        );
+        // Not a key name, so just the chars
+        check(
+            "<Foo><C-ab>",
+            &"<Foo><C-ab>".chars().map(Key::Char).collect::<Vec<_>>(),
+        );
+        check("<", &[Key::Char('<')]);
+        check("<<Esc>", &[Key::Char('<'), Key::Esc]);
+    }
+}
@@ -4,6 +4,7 @@ use std::path::PathBuf;
 
 use crate::backend::buffer::Buffer;
 use crate::backend::editor::Editor;
+use crate::utils::index;
 // Some traits that components should implement
 use crate::{
     cli::{Cli, Target},
@@ -17,6 +18,10 @@ mod colors;
 mod dashboard;
 mod editor;
 mod finder;
+mod keys;
+mod replace;
+mod tabbar;
+mod window;
 
 type ZedError = Result<(), Error>;
 
@@ -50,13 +55,25 @@ pub trait Component {
     ) -> Result<Self::WidgetReturn, Error>;
 }
 
-fn render_editor<T: Write>(file_path: PathBuf, term: &mut Terminal<T>, k: KeyIterator) -> ZedError {
-    let b = Buffer::new().set_path(&file_path);
-    let e = Editor::new();
+fn render_editor<T: Write>(
+    cli: &Cli,
+    dir: PathBuf,
+    file_path: PathBuf,
+    line: usize,
+    term: &mut Terminal<T>,
+    k: KeyIterator,
+) -> ZedError {
+    let b = Buffer::new().set_path(file_path);
+    let e = Editor::new()
+        .set_dir(dir)
+        .set_backup(cli.backup)
+        .set_save_on_exit(cli.save_on_exit)
+        .set_clipboard(cli.clipboard);
 
     // Should probably rename this to something else
     let mut editor = editor::Editor::new().set_editor(e);
-    editor.push_buf(&b);
+    editor.push_buf(b);
+    editor.goto_line(line);
 
     term.switch_screen().unwrap();
     editor.render(term, k).unwrap();
@@ -67,6 +84,8 @@ pub fn render_ui<T: Write>(cli: &Cli, term: &mut Terminal<T>, keys: KeyIterator)
     // Manage the User Interface
     match &cli.target {
         Target::Dir(x) => {
+            // Start finding files while the dashboard is up
+            index::get(x, false);
             term.switch_screen().unwrap();
             let mut dashboard = dashboard::Dashboard::new().set_dir(x.to_path_buf());
 
@@ -74,7 +93,11 @@ pub fn render_ui<T: Write>(cli: &Cli, term: &mut Terminal<T>, keys: KeyIterator)
                 Ok(t) => match t {
                     Target::File(m) => {
                         term.set_cursor_to(1, 1).unwrap();
-                        render_editor(m, term, keys).unwrap();
+                        render_editor(cli, x.to_path_buf(), m, 1, term, keys).unwrap();
+                    }
+                    Target::Line(m, line) => {
+                        term.set_cursor_to(1, 1).unwrap();
+                        render_editor(cli, x.to_path_buf(), m, line, term, keys).unwrap();
                     }
                     _ => (),
                 },
@@ -83,7 +106,18 @@ pub fn render_ui<T: Write>(cli: &Cli, term: &mut Terminal<T>, keys: KeyIterator)
                 }
             }
         }
-        Target::File(x) => render_editor(x.to_path_buf(), term, keys).unwrap(),
+        Target::File(x) | Target::Line(x, _) => {
+            let dir = match x.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            index::get(&dir, false);
+            let line = match cli.target {
+                Target::Line(_, l) => l,
+                _ => 1,
+            };
+            render_editor(cli, dir, x.to_path_buf(), line, term, keys).unwrap()
+        }
         Target::Empty => (),
     }
 
@@ -0,0 +1,487 @@
+// Search and replace across every file under a directory
+// Matches are listed with their line and what they'd become,
+// the ones left ticked are returned to be replaced.
+use super::Component;
+use crate::backend::buffer::Buffer;
+use crate::backend::search::Substitute;
+use crate::error::Error;
+use crate::utils::index;
+use std::fs;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use zui_core::color::{self, fg, Color};
+use zui_core::key::{Key, KeyIterator};
+use zui_core::term::Terminal;
+
+// Stop looking after this many matches
+const MAX_MATCHES: usize = 5000;
+// First row of the list, below the header, the two fields and the status
+const LIST_TOP: u16 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Search,
+    Replace,
+    List,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Row {
+    File(usize),
+    Match(usize),
+}
+
+struct Match {
+    // Index into `files`
+    file: usize,
+    // 0-based
+    line: usize,
+    // Chars in the file
+    range: Range<usize>,
+    // Where the match starts on its line
+    col: usize,
+    // The whole line, for context
+    text: String,
+    replacement: String,
+    ticked: bool,
+}
+
+// What to change in one file, in the order of the file
+pub struct FileEdits {
+    pub path: PathBuf,
+    pub edits: Vec<(Range<usize>, String)>,
+    // Of the text searched, the edits only fit while it is unchanged
+    pub hash: u64,
+}
+
+pub struct ReplacePanel {
+    dir: PathBuf,
+    // Text of the buffers open in the editor, searched instead of the file
+    open: Vec<(PathBuf, String)>,
+    search: String,
+    replace: String,
+    field: Field,
+    files: Vec<PathBuf>,
+    // Content hash of each file when it was searched
+    hashes: Vec<u64>,
+    matches: Vec<Match>,
+    rows: Vec<Row>,
+    selected: usize,
+    first_row: usize,
+    // Error or count shown above the list
+    status: String,
+}
+
+impl ReplacePanel {
+    pub fn set_dir(mut self, dir: PathBuf) -> ReplacePanel {
+        self.dir = dir;
+        self
+    }
+
+    pub fn set_open(mut self, open: Vec<(PathBuf, String)>) -> ReplacePanel {
+        self.open = open
+            .into_iter()
+            .map(|(p, text)| (fs::canonicalize(&p).unwrap_or(p), text))
+            .collect();
+        self
+    }
+
+    fn open_text(&self, p: &Path) -> Option<String> {
+        let full = fs::canonicalize(p).ok()?;
+        let (_, text) = self.open.iter().find(|(o, _)| *o == full)?;
+        Some(text.clone())
+    }
+
+    fn clear_results(&mut self) {
+        self.files.clear();
+        self.hashes.clear();
+        self.matches.clear();
+        self.rows.clear();
+        self.selected = 0;
+        self.first_row = 0;
+        self.status = String::from("Enter to search");
+    }
+
+    fn run_search(&mut self) {
+        self.clear_results();
+        if self.search.is_empty() {
+            return;
+        }
+        let sub = Substitute::new(&self.search, &self.replace);
+        let re = match sub.regex() {
+            Ok(re) => re,
+            Err(e) => {
+                self.status = format!("{}", e);
+                return;
+            }
+        };
+
+        for path in index::walk(&self.dir, false) {
+            if self.matches.len() >= MAX_MATCHES {
+                break;
+            }
+            if !path.is_file() {
+                continue;
+            }
+            let text = match self.open_text(&path) {
+                Some(t) => t,
+                None => match fs::read_to_string(&path) {
+                    Ok(t) => t,
+                    Err(_) => continue,
+                },
+            };
+            // Binary files
+            if text.contains('\0') {
+                continue;
+            }
+
+            let buf = Buffer::new().set_text(&text);
+            let found = sub.matches(&re, &buf, 0, buf.line_count.saturating_sub(1));
+            if found.is_empty() {
+                continue;
+            }
+            let file = self.files.len();
+            self.files.push(path);
+            self.hashes.push(buf.content_hash());
+            self.rows.push(Row::File(file));
+            for (range, replacement) in found {
+                let line = buf.rope.char_to_line(range.start);
+                let start = buf.rope.line_to_char(line);
+                self.rows.push(Row::Match(self.matches.len()));
+                self.matches.push(Match {
+                    file,
+                    line,
+                    col: range.start - start,
+                    range,
+                    text: buf
+                        .rope
+                        .slice(start..start + buf.line_len(line))
+                        .to_string(),
+                    replacement,
+                    ticked: true,
+                });
+            }
+        }
+
+        self.status = match self.matches.len() {
+            0 => format!("Pattern not found: {}", self.search),
+            n => format!(
+                "{} matches in {} files{}",
+                n,
+                self.files.len(),
+                if n >= MAX_MATCHES {
+                    " (stopped looking)"
+                } else {
+                    ""
+                }
+            ),
+        };
+    }
+
+    // Move the highlighted match, keeping it on screen
+    fn select<T: Write>(&mut self, term: &Terminal<T>, down: bool) {
+        if self.matches.is_empty() {
+            return;
+        }
+        if down {
+            self.selected = (self.selected + 1).min(self.matches.len() - 1);
+        } else {
+            self.selected = self.selected.saturating_sub(1);
+        }
+
+        let height = self.list_height(term);
+        let row = self.selected_row();
+        // Show the file name above the first match of a file
+        let top = match row.checked_sub(1).map(|r| self.rows[r]) {
+            Some(Row::File(_)) => row - 1,
+            _ => row,
+        };
+        if top < self.first_row {
+            self.first_row = top;
+        } else if row >= self.first_row + height {
+            self.first_row = row + 1 - height;
+        }
+    }
+
+    fn selected_row(&self) -> usize {
+        self.rows
+            .iter()
+            .position(|r| *r == Row::Match(self.selected))
+            .unwrap_or(0)
+    }
+
+    // Rows for the list, between the status and the help on the last row
+    fn list_height<T: Write>(&self, term: &Terminal<T>) -> usize {
+        let (_, y) = term.get_size();
+        y.saturating_sub(LIST_TOP) as usize
+    }
+
+    // Ticked matches, grouped by file
+    fn ticked(&self) -> Vec<FileEdits> {
+        let mut out: Vec<FileEdits> = Vec::new();
+        for m in self.matches.iter().filter(|m| m.ticked) {
+            let edit = (m.range.clone(), m.replacement.clone());
+            let path = &self.files[m.file];
+            match out.last_mut() {
+                Some(f) if &f.path == path => f.edits.push(edit),
+                _ => out.push(FileEdits {
+                    path: path.clone(),
+                    edits: vec![edit],
+                    hash: self.hashes[m.file],
+                }),
+            }
+        }
+        out
+    }
+
+    fn draw_match<T: Write>(&self, term: &mut Terminal<T>, m: &Match, width: usize) {
+        let chars: Vec<char> = m.text.chars().collect();
+        let end = (m.col + m.range.len()).min(chars.len());
+        let before: String = chars[..m.col].iter().collect();
+        let matched: String = chars[m.col..end].iter().collect();
+        let after: String = chars[end..].iter().collect();
+        let parts = [
+            (
+                format!("{}", fg(Color::RGB(153, 153, 102))),
+                format!(
+                    "  [{}] {:>4}: ",
+                    if m.ticked { 'x' } else { ' ' },
+                    m.line + 1
+                ),
+            ),
+            (format!("{}", fg(Color::Reset)), before),
+            (format!("{}", fg(Color::Red)), matched),
+            (format!("{}", fg(Color::GreenLight)), m.replacement.clone()),
+            (format!("{}", fg(Color::Reset)), after),
+        ];
+        draw_row(term, width, &parts);
+    }
+}
+
+// Print `parts` of (color, text) on the current row, cut at `width`
+// and padded to it
+fn draw_row<T: Write>(term: &mut Terminal<T>, width: usize, parts: &[(String, String)]) {
+    let mut out = String::new();
+    let mut used = 0;
+    for (color, text) in parts {
+        out.push_str(color);
+        for c in text.chars() {
+            if used >= width {
+                break;
+            }
+            match c {
+                '\t' => out.push(' '),
+                '\n' => out.push_str("^M"),
+                c => out.push(c),
+            }
+            used += 1;
+        }
+    }
+    while used < width {
+        out.push(' ');
+        used += 1;
+    }
+    out.push_str(&format!("{}", fg(Color::Reset)));
+    term.print(out).unwrap();
+}
+
+// Make `edits`, in the order of the buffer, as one undo step
+pub fn apply(buf: &mut Buffer, edits: &[(Range<usize>, String)]) {
+    let cursor = edits.first().map_or(0, |(r, _)| r.start);
+    buf.begin_transaction(cursor);
+    // From the end so the earlier ranges stay put
+    for (range, text) in edits.iter().rev() {
+        buf.delete(range.clone());
+        buf.insert_str(range.start, text);
+    }
+    buf.commit_transaction(cursor);
+}
+
+impl Component for ReplacePanel {
+    type Widget = ReplacePanel;
+
+    // The ticked matches, nothing when cancelled
+    type WidgetReturn = Vec<FileEdits>;
+
+    fn new() -> Self::Widget {
+        ReplacePanel {
+            dir: PathBuf::new(),
+            open: Vec::new(),
+            search: String::new(),
+            replace: String::new(),
+            field: Field::Search,
+            files: Vec::new(),
+            hashes: Vec::new(),
+            matches: Vec::new(),
+            rows: Vec::new(),
+            selected: 0,
+            first_row: 0,
+            status: String::from("Enter to search"),
+        }
+    }
+
+    fn destroy<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        match term.clear_screen() {
+            Ok(_) => Ok(()),
+            Err(_e) => Err(Error::CouldNotRender),
+        }
+    }
+
+    fn view<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        let (x, _) = term.get_size();
+        let width = x as usize;
+        let header = format!("{}", color::bg(Color::RGB(61, 61, 41)));
+        let reset = format!("{}", color::bg(Color::Reset));
+        let dim = format!("{}", fg(Color::RGB(153, 153, 102)));
+        let plain = format!("{}", fg(Color::Reset));
+
+        term.hide_cursor().unwrap();
+        term.clear_screen().unwrap();
+
+        term.set_cursor_to(1, 1).unwrap();
+        term.print(&header).unwrap();
+        let title = format!(" Replace in {}", self.dir.display());
+        draw_row(term, width, &[(plain.clone(), title)]);
+        term.print(&reset).unwrap();
+
+        let fields = [
+            (" Search:  ", &self.search, Field::Search),
+            (" Replace: ", &self.replace, Field::Replace),
+        ];
+        for (row, (label, text, field)) in fields.iter().enumerate() {
+            term.set_cursor_to(1, row as u16 + 2).unwrap();
+            let label = if self.field == *field {
+                (plain.clone(), label.to_string())
+            } else {
+                (dim.clone(), label.to_string())
+            };
+            draw_row(term, width, &[label, (plain.clone(), text.to_string())]);
+        }
+
+        term.set_cursor_to(1, 4).unwrap();
+        draw_row(term, width, &[(dim.clone(), format!(" {}", self.status))]);
+
+        let height = self.list_height(term);
+        let selected = self.selected_row();
+        for (i, row) in self
+            .rows
+            .iter()
+            .enumerate()
+            .skip(self.first_row)
+            .take(height)
+        {
+            term.set_cursor_to(1, LIST_TOP + (i - self.first_row) as u16)
+                .unwrap();
+            if self.field == Field::List && i == selected {
+                term.print(color::bg(Color::RGB(92, 92, 61))).unwrap();
+            }
+            match row {
+                Row::File(f) => {
+                    let p = &self.files[*f];
+                    let name = p.strip_prefix(&self.dir).unwrap_or(p);
+                    draw_row(
+                        term,
+                        width,
+                        &[(plain.clone(), format!(" {}", name.display()))],
+                    );
+                }
+                Row::Match(m) => self.draw_match(term, &self.matches[*m], width),
+            }
+            term.print(&reset).unwrap();
+        }
+
+        let (_, y) = term.get_size();
+        term.set_cursor_to(1, y).unwrap();
+        let help = " Tab next field  Enter search/replace  Space tick  a tick all  Esc cancel";
+        draw_row(term, width, &[(dim, help.to_string())]);
+
+        // The cursor sits in the field being typed in, or on the match
+        match self.field {
+            Field::Search => {
+                let x = self.search.chars().count() + 11;
+                term.set_cursor_to(x as u16, 2).unwrap();
+            }
+            Field::Replace => {
+                let x = self.replace.chars().count() + 11;
+                term.set_cursor_to(x as u16, 3).unwrap();
+            }
+            Field::List => {
+                let row = selected.saturating_sub(self.first_row) as u16;
+                term.set_cursor_to(4, LIST_TOP + row).unwrap();
+            }
+        }
+        term.show_cursor().unwrap();
+        Ok(())
+    }
+
+    fn handle_key<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> Result<Self::WidgetReturn, Error> {
+        for key in keys.clone() {
+            match (self.field, key) {
+                (_, Key::Esc) => {
+                    self.destroy(term)?;
+                    return Ok(Vec::new());
+                }
+                (Field::Search, Key::Tab) => self.field = Field::Replace,
+                (Field::Replace, Key::Tab) if !self.matches.is_empty() => self.field = Field::List,
+                (_, Key::Tab) => self.field = Field::Search,
+                (Field::List, Key::Enter) => {
+                    self.destroy(term)?;
+                    return Ok(self.ticked());
+                }
+                (_, Key::Enter) => {
+                    self.run_search();
+                    if !self.matches.is_empty() {
+                        self.field = Field::List;
+                    }
+                }
+                (Field::List, Key::Up) | (Field::List, Key::Char('k')) => self.select(term, false),
+                (Field::List, Key::Down) | (Field::List, Key::Char('j')) => self.select(term, true),
+                (Field::List, Key::Char(' ')) => {
+                    if let Some(m) = self.matches.get_mut(self.selected) {
+                        m.ticked = !m.ticked;
+                    }
+                }
+                (Field::List, Key::Char('a')) => {
+                    let all = self.matches.iter().all(|m| m.ticked);
+                    for m in self.matches.iter_mut() {
+                        m.ticked = !all;
+                    }
+                }
+                (Field::List, _) => continue,
+                (field, Key::Backspace) => {
+                    if field == Field::Search {
+                        self.search.pop();
+                    } else {
+                        self.replace.pop();
+                    }
+                    self.clear_results();
+                }
+                (field, Key::Char(c)) => {
+                    if field == Field::Search {
+                        self.search.push(c);
+                    } else {
+                        self.replace.push(c);
+                    }
+                    self.clear_results();
+                }
+                _ => continue,
+            }
+            self.view(term)?;
+        }
+        Ok(Vec::new())
+    }
+
+    fn render<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> Result<Self::WidgetReturn, Error> {
+        self.view(term)?;
+        self.handle_key(term, keys)
+    }
+}
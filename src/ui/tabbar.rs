@@ -0,0 +1,157 @@
+use super::Component;
+use crate::backend::editor;
+use crate::error::Error;
+use std::io::Write;
+use zui_core::color::{self, Color};
+use zui_core::key::{Key, KeyIterator};
+use zui_core::term::Terminal;
+
+// What the editor should do after a tab binding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TabAction {
+    Next,
+    Prev,
+    Close,
+    Goto(usize),
+    Nothing,
+}
+
+// What the key after `SPC t` does
+pub fn action(key: Key) -> TabAction {
+    match key {
+        Key::Char('n') | Key::Char('l') => TabAction::Next,
+        Key::Char('p') | Key::Char('h') => TabAction::Prev,
+        Key::Char('x') => TabAction::Close,
+        Key::Char(c) => match c.to_digit(10) {
+            Some(n) if n > 0 => TabAction::Goto(n as usize),
+            _ => TabAction::Nothing,
+        },
+        _ => TabAction::Nothing,
+    }
+}
+
+struct Tab {
+    id: usize,
+    name: String,
+    icon: &'static str,
+    modified: bool,
+}
+
+// Row of open buffers above the text
+pub struct TabBar {
+    tabs: Vec<Tab>,
+    current: Option<usize>,
+}
+
+impl TabBar {
+    // Refresh the tabs from the buffers in the editor
+    pub fn set_tabs(&mut self, e: &editor::Editor) {
+        self.tabs = e
+            .buffers
+            .iter()
+            .map(|b| Tab {
+                id: b.id,
+                name: b.display_name(),
+                icon: b.icon(),
+                modified: b.is_modified(),
+            })
+            .collect();
+        self.current = e.cur_buf;
+    }
+
+    // Id of the buffer shown in the nth tab, counting from 1
+    pub fn tab_id(&self, n: usize) -> Option<usize> {
+        self.tabs.get(n.wrapping_sub(1)).map(|t| t.id)
+    }
+}
+
+impl Component for TabBar {
+    type Widget = TabBar;
+
+    // WidgetReturn
+    type WidgetReturn = TabAction;
+
+    fn new() -> Self::Widget {
+        TabBar {
+            tabs: Vec::new(),
+            current: None,
+        }
+    }
+
+    fn destroy<T: Write>(&mut self, _term: &mut Terminal<T>) -> super::ZedError {
+        Ok(())
+    }
+
+    fn view<T: Write>(&mut self, term: &mut Terminal<T>) -> super::ZedError {
+        let (x, _) = term.get_size();
+        let mut width = 0;
+
+        term.hide_cursor().unwrap();
+        term.set_cursor_to(1, 1).unwrap();
+
+        for (i, tab) in self.tabs.iter().enumerate() {
+            let mut label = String::new();
+            label.push(' ');
+            if !tab.icon.is_empty() {
+                label.push_str(tab.icon);
+                label.push(' ');
+            }
+            label.push_str(&format!("{}:{}", i + 1, tab.name));
+            if tab.modified {
+                label.push_str(" [+]");
+            }
+            label.push(' ');
+
+            // Don't wrap onto the text
+            let left = (x as usize).saturating_sub(width);
+            if left == 0 {
+                break;
+            }
+            let label: String = label.chars().take(left).collect();
+            width += label.chars().count();
+
+            if self.current == Some(tab.id) {
+                term.print(color::bg(Color::RGB(61, 61, 41))).unwrap();
+            } else {
+                term.print(color::bg(Color::RGB(30, 30, 20))).unwrap();
+                term.print(color::fg(Color::RGB(153, 153, 102))).unwrap();
+            }
+            term.print(label).unwrap();
+            term.print(color::bg(Color::Reset)).unwrap();
+            term.print(color::fg(Color::Reset)).unwrap();
+        }
+
+        // Rest of the bar
+        let mut rest = String::new();
+        for _ in width..x as usize {
+            rest.push(' ');
+        }
+        term.print(color::bg(Color::RGB(30, 30, 20))).unwrap();
+        term.print(rest).unwrap();
+        term.print(color::bg(Color::Reset)).unwrap();
+
+        term.show_cursor().unwrap();
+        Ok(())
+    }
+
+    // Reads the key after `SPC t`
+    fn handle_key<T: Write>(
+        &mut self,
+        _term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> Result<Self::WidgetReturn, Error> {
+        match keys.clone().next() {
+            Some(key) => Ok(action(key)),
+            None => Ok(TabAction::Nothing),
+        }
+    }
+
+    fn render<T: Write>(
+        &mut self,
+        term: &mut Terminal<T>,
+        keys: KeyIterator,
+    ) -> Result<Self::WidgetReturn, Error> {
+        self.view(term).unwrap();
+        self.handle_key(term, keys)
+    }
+}
@@ -0,0 +1,381 @@
+// Split windows
+// Every window shows a buffer with its own cursor and scroll position.
+// Windows are laid out by a tree of splits.
+use std::mem;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub id: usize,
+    // Buffer id
+    pub buf: usize,
+    pub current_line: usize,
+    pub current_index: usize,
+    pub shown_lines: (usize, usize),
+    // First screen column shown, for lines wider than the window
+    pub shown_col: usize,
+}
+
+impl Window {
+    pub fn new(id: usize, buf: usize) -> Window {
+        Window {
+            id,
+            buf,
+            current_line: 1,
+            current_index: 1,
+            shown_lines: (0, 1),
+            shown_col: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitDir {
+    // One window above the other
+    Horizontal,
+    // Side by side
+    Vertical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+#[derive(Debug)]
+pub enum Layout {
+    Window(usize),
+    Split {
+        dir: SplitDir,
+        // Percentage of the space given to `first`
+        ratio: u16,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    // Split window `win`, putting `new` below or to the right of it
+    pub fn split(&mut self, win: usize, new: usize, dir: SplitDir) -> bool {
+        match self {
+            Layout::Window(id) if *id == win => {
+                *self = Layout::Split {
+                    dir,
+                    ratio: 50,
+                    first: Box::new(Layout::Window(win)),
+                    second: Box::new(Layout::Window(new)),
+                };
+                true
+            }
+            Layout::Window(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.split(win, new, dir) || second.split(win, new, dir)
+            }
+        }
+    }
+
+    // Remove window `win`, its sibling takes the space
+    pub fn remove(&mut self, win: usize) -> bool {
+        if let Layout::Split { first, second, .. } = self {
+            let sibling = if matches!(**first, Layout::Window(id) if id == win) {
+                Some(mem::replace(&mut **second, Layout::Window(0)))
+            } else if matches!(**second, Layout::Window(id) if id == win) {
+                Some(mem::replace(&mut **first, Layout::Window(0)))
+            } else {
+                None
+            };
+
+            match sibling {
+                Some(s) => {
+                    *self = s;
+                    true
+                }
+                None => first.remove(win) || second.remove(win),
+            }
+        } else {
+            false
+        }
+    }
+
+    pub fn contains(&self, win: usize) -> bool {
+        match self {
+            Layout::Window(id) => *id == win,
+            Layout::Split { first, second, .. } => first.contains(win) || second.contains(win),
+        }
+    }
+
+    // Grow (or shrink, with a negative `delta`) window `win` along `dir`
+    pub fn resize(&mut self, win: usize, dir: SplitDir, delta: i16) -> bool {
+        if let Layout::Split {
+            dir: d,
+            ratio,
+            first,
+            second,
+        } = self
+        {
+            // Prefer the innermost split
+            if first.resize(win, dir, delta) || second.resize(win, dir, delta) {
+                return true;
+            }
+            if *d == dir && (first.contains(win) || second.contains(win)) {
+                let delta = if first.contains(win) { delta } else { -delta };
+                *ratio = (*ratio as i16 + delta).clamp(10, 90) as u16;
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn equalize(&mut self) {
+        if let Layout::Split {
+            ratio,
+            first,
+            second,
+            ..
+        } = self
+        {
+            *ratio = 50;
+            first.equalize();
+            second.equalize();
+        }
+    }
+
+    pub fn windows(&self) -> Vec<usize> {
+        match self {
+            Layout::Window(id) => vec![*id],
+            Layout::Split { first, second, .. } => {
+                let mut w = first.windows();
+                w.extend(second.windows());
+                w
+            }
+        }
+    }
+
+    // Where each window goes in `area`
+    // Side by side windows are kept apart by a one column separator
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        match self {
+            Layout::Window(id) => vec![(*id, area)],
+            Layout::Split {
+                dir,
+                ratio,
+                first,
+                second,
+            } => {
+                let (a, b) = match dir {
+                    SplitDir::Horizontal => {
+                        let h = part(area.height, *ratio, 0);
+                        (
+                            Rect { height: h, ..area },
+                            Rect {
+                                y: area.y + h,
+                                height: area.height - h,
+                                ..area
+                            },
+                        )
+                    }
+                    SplitDir::Vertical => {
+                        let w = part(area.width, *ratio, 1);
+                        (
+                            Rect { width: w, ..area },
+                            Rect {
+                                x: area.x + w + 1,
+                                width: area.width.saturating_sub(w + 1),
+                                ..area
+                            },
+                        )
+                    }
+                };
+                let mut rects = first.rects(a);
+                rects.extend(second.rects(b));
+                rects
+            }
+        }
+    }
+}
+
+// Size of the first half of a split, leaving room for `gap`
+fn part(size: u16, ratio: u16, gap: u16) -> u16 {
+    let first = (size.saturating_sub(gap) as u32 * ratio as u32 / 100) as u16;
+    first.max(1).min(size.saturating_sub(gap + 1).max(1))
+}
+
+// The window next to `from` in direction `dir`
+pub fn neighbour(rects: &[(usize, Rect)], from: usize, dir: Direction) -> Option<usize> {
+    let a = rects.iter().find(|(id, _)| *id == from)?.1;
+
+    rects
+        .iter()
+        .filter(|(id, r)| {
+            *id != from
+                && match dir {
+                    Direction::Left => {
+                        r.x + r.width < a.x && overlaps(a.y, a.height, r.y, r.height)
+                    }
+                    Direction::Right => {
+                        r.x > a.x + a.width && overlaps(a.y, a.height, r.y, r.height)
+                    }
+                    Direction::Up => r.y + r.height <= a.y && overlaps(a.x, a.width, r.x, r.width),
+                    Direction::Down => {
+                        r.y >= a.y + a.height && overlaps(a.x, a.width, r.x, r.width)
+                    }
+                }
+        })
+        .min_by_key(|(_, r)| match dir {
+            Direction::Left => a.x - (r.x + r.width),
+            Direction::Right => r.x - (a.x + a.width),
+            Direction::Up => a.y - (r.y + r.height),
+            Direction::Down => r.y - (a.y + a.height),
+        })
+        .map(|(id, _)| *id)
+}
+
+fn overlaps(a: u16, a_len: u16, b: u16, b_len: u16) -> bool {
+    a < b + b_len && b < a + a_len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect {
+        x: 1,
+        y: 2,
+        width: 81,
+        height: 20,
+    };
+
+    fn rect(layout: &Layout, win: usize) -> Rect {
+        layout
+            .rects(AREA)
+            .into_iter()
+            .find(|(id, _)| *id == win)
+            .unwrap()
+            .1
+    }
+
+    // 1 | 2
+    // --+
+    // 3 |
+    fn nested() -> Layout {
+        let mut layout = Layout::Window(1);
+        assert!(layout.split(1, 2, SplitDir::Vertical));
+        assert!(layout.split(1, 3, SplitDir::Horizontal));
+        layout
+    }
+
+    #[test]
+    fn split() {
+        let mut layout = Layout::Window(1);
+        assert!(!layout.split(2, 3, SplitDir::Vertical));
+        assert_eq!(layout.rects(AREA), vec![(1, AREA)]);
+
+        let layout = nested();
+        assert_eq!(layout.windows(), vec![1, 3, 2]);
+        // The separator column goes between side by side windows
+        assert_eq!(
+            rect(&layout, 1),
+            Rect {
+                x: 1,
+                y: 2,
+                width: 40,
+                height: 10
+            }
+        );
+        assert_eq!(
+            rect(&layout, 3),
+            Rect {
+                x: 1,
+                y: 12,
+                width: 40,
+                height: 10
+            }
+        );
+        assert_eq!(
+            rect(&layout, 2),
+            Rect {
+                x: 42,
+                y: 2,
+                width: 40,
+                height: 20
+            }
+        );
+    }
+
+    #[test]
+    fn close() {
+        let mut layout = nested();
+        // The only window left is never removed
+        let mut single = Layout::Window(1);
+        assert!(!single.remove(1));
+        assert!(!layout.remove(4));
+
+        // Its sibling takes the space
+        assert!(layout.remove(3));
+        assert_eq!(layout.windows(), vec![1, 2]);
+        assert_eq!(rect(&layout, 1).height, 20);
+
+        // Closing the last window of a split leaves the other side alone
+        assert!(layout.remove(1));
+        assert!(matches!(layout, Layout::Window(2)));
+        assert_eq!(layout.rects(AREA), vec![(2, AREA)]);
+
+        let mut layout = nested();
+        assert!(layout.remove(2));
+        assert_eq!(layout.windows(), vec![1, 3]);
+        assert_eq!(rect(&layout, 1).width, AREA.width);
+    }
+
+    #[test]
+    fn resize() {
+        let mut layout = nested();
+        // The innermost split along that direction
+        assert!(layout.resize(3, SplitDir::Horizontal, 20));
+        assert_eq!(rect(&layout, 1).height, 6);
+        assert!(layout.resize(3, SplitDir::Vertical, -100));
+        assert_eq!(rect(&layout, 1).width, 8);
+        assert!(!Layout::Window(1).resize(1, SplitDir::Vertical, 10));
+        layout.equalize();
+        assert_eq!(
+            rect(&layout, 1),
+            Rect {
+                x: 1,
+                y: 2,
+                width: 40,
+                height: 10
+            }
+        );
+    }
+
+    #[test]
+    fn focus_neighbour() {
+        let rects = nested().rects(AREA);
+        assert_eq!(neighbour(&rects, 1, Direction::Down), Some(3));
+        assert_eq!(neighbour(&rects, 3, Direction::Up), Some(1));
+        assert_eq!(neighbour(&rects, 1, Direction::Right), Some(2));
+        assert_eq!(neighbour(&rects, 3, Direction::Right), Some(2));
+        assert_eq!(neighbour(&rects, 2, Direction::Left), Some(1));
+        // Nothing past the edge
+        assert_eq!(neighbour(&rects, 2, Direction::Right), None);
+        assert_eq!(neighbour(&rects, 1, Direction::Up), None);
+        assert_eq!(neighbour(&rects, 3, Direction::Down), None);
+        assert_eq!(neighbour(&rects, 5, Direction::Down), None);
+
+        // The closest one in that direction
+        let mut layout = nested();
+        assert!(layout.split(2, 4, SplitDir::Vertical));
+        let rects = layout.rects(AREA);
+        assert_eq!(neighbour(&rects, 1, Direction::Right), Some(2));
+        assert_eq!(neighbour(&rects, 4, Direction::Left), Some(2));
+    }
+}
@@ -0,0 +1,254 @@
+// fzf style fuzzy matching
+// The query's chars have to show up in order. Among the ways they can,
+// the best one is picked: matches right after a separator, at a word
+// or camelCase boundary, in a row, or in the file name score higher,
+// and gaps between them cost a little.
+// Queries without uppercase letters match either case.
+
+const SCORE_MATCH: i64 = 16;
+const GAP_START: i64 = -3;
+const GAP_EXTENSION: i64 = -1;
+const BONUS_CONSECUTIVE: i64 = 8;
+// Start of a path component
+const BONUS_SLASH: i64 = 10;
+// After `_`, `-`, `.` or a space
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CAMEL: i64 = 7;
+// Anywhere after the last `/`
+const BONUS_BASENAME: i64 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub score: i64,
+    // Char indices of the matched chars in the text
+    pub positions: Vec<usize>,
+}
+
+fn fold(c: char, ignore_case: bool) -> char {
+    if ignore_case {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+// How well `query` matches `text`, None when it doesn't
+pub fn score(query: &str, text: &str) -> Option<Match> {
+    Matcher::new(query).score(text)
+}
+
+// Matches one query against many texts, reusing its buffers
+pub struct Matcher {
+    // The query, folded unless it has uppercase letters
+    query: Vec<char>,
+    ignore_case: bool,
+    raw: Vec<char>,
+    text: Vec<char>,
+    bonus: Vec<i64>,
+    // Best score with the previous query char matched at each text char,
+    // and the same for the current one
+    prev: Vec<Option<i64>>,
+    row: Vec<Option<i64>>,
+    // Row by row, where query[i - 1] was matched for query[i] at each
+    // text char
+    from: Vec<u32>,
+}
+
+impl Matcher {
+    pub fn new(query: &str) -> Matcher {
+        let ignore_case = !query.chars().any(|c| c.is_uppercase());
+        Matcher {
+            query: query.chars().map(|c| fold(c, ignore_case)).collect(),
+            ignore_case,
+            raw: Vec::new(),
+            text: Vec::new(),
+            bonus: Vec::new(),
+            prev: Vec::new(),
+            row: Vec::new(),
+            from: Vec::new(),
+        }
+    }
+
+    pub fn score(&mut self, text: &str) -> Option<Match> {
+        if self.query.is_empty() {
+            return Some(Match {
+                score: 0,
+                positions: Vec::new(),
+            });
+        }
+        self.raw.clear();
+        self.raw.extend(text.chars());
+        let ignore_case = self.ignore_case;
+        self.text.clear();
+        self.text
+            .extend(self.raw.iter().map(|c| fold(*c, ignore_case)));
+        let (q, t, raw) = (&self.query, &self.text, &self.raw);
+
+        // Cheap check before scoring
+        let mut rest = t.iter();
+        if !q.iter().all(|c| rest.any(|x| x == c)) {
+            return None;
+        }
+
+        let basename = raw.iter().rposition(|c| *c == '/').map_or(0, |i| i + 1);
+        self.bonus.clear();
+        self.bonus.extend((0..raw.len()).map(|j| {
+            let b = match j.checked_sub(1).map(|p| raw[p]) {
+                None | Some('/') => BONUS_SLASH,
+                Some('_') | Some('-') | Some('.') | Some(' ') => BONUS_BOUNDARY,
+                Some(p) if p.is_lowercase() && raw[j].is_uppercase() => BONUS_CAMEL,
+                _ => 0,
+            };
+            if j >= basename {
+                b + BONUS_BASENAME
+            } else {
+                b
+            }
+        }));
+        let bonus = &self.bonus;
+
+        let n = t.len();
+        self.from.clear();
+        self.from.resize(q.len() * n, 0);
+        self.prev.clear();
+        self.prev
+            .extend((0..n).map(|j| Some(SCORE_MATCH + bonus[j]).filter(|_| t[j] == q[0])));
+        for (i, qc) in q.iter().enumerate().skip(1) {
+            let (prev, row) = (&self.prev, &mut self.row);
+            let from = &mut self.from[i * n..(i + 1) * n];
+            row.clear();
+            row.resize(n, None);
+            // Best way to reach j with a gap before it, and where it came from
+            let mut gap: Option<(i64, usize)> = None;
+            for j in 1..n {
+                if j >= 2 {
+                    let start = prev[j - 2].map(|s| (s + GAP_START, j - 2));
+                    let extend = gap.map(|(s, k)| (s + GAP_EXTENSION, k));
+                    gap = match (start, extend) {
+                        (Some(a), Some(b)) if b.0 > a.0 => Some(b),
+                        (None, b) => b,
+                        (a, _) => a,
+                    };
+                }
+                if t[j] != *qc {
+                    continue;
+                }
+                let next = prev[j - 1].map(|s| (s + BONUS_CONSECUTIVE, j - 1));
+                let best = match (next, gap) {
+                    (Some(a), Some(b)) if b.0 > a.0 => Some(b),
+                    (None, b) => b,
+                    (a, _) => a,
+                };
+                if let Some((s, k)) = best {
+                    row[j] = Some(s + SCORE_MATCH + bonus[j]);
+                    from[j] = k as u32;
+                }
+            }
+            std::mem::swap(&mut self.prev, &mut self.row);
+        }
+
+        // The best end, the earliest one on ties
+        let (mut j, score) = self
+            .prev
+            .iter()
+            .enumerate()
+            .filter_map(|(j, s)| s.map(|s| (j, s)))
+            .max_by_key(|(j, s)| (*s, std::cmp::Reverse(*j)))?;
+
+        let mut positions = vec![0; q.len()];
+        for i in (0..q.len()).rev() {
+            positions[i] = j;
+            j = self.from[i * n + j] as usize;
+        }
+        Some(Match { score, positions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn best<'a>(query: &str, paths: &[&'a str]) -> Vec<&'a str> {
+        let mut ranked: Vec<(i64, &str)> = paths
+            .iter()
+            .filter_map(|p| score(query, p).map(|m| (m.score, *p)))
+            .collect();
+        ranked.sort_by_key(|(s, _)| -s);
+        ranked.into_iter().map(|(_, p)| p).collect()
+    }
+
+    #[test]
+    fn ranks_file_name_matches_first() {
+        let paths = [
+            "src/backend/undo.rs",
+            "src/ui/bufferline/render.rs",
+            "src/backend/buffer.rs",
+            "benches/ui/frames.rs",
+        ];
+        assert_eq!(best("bufrs", &paths)[0], "src/backend/buffer.rs");
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(score("buf", "src/Buffer.rs").is_some());
+        assert!(score("Buf", "src/Buffer.rs").is_some());
+        assert!(score("Buf", "src/buffer.rs").is_none());
+    }
+
+    #[test]
+    fn no_match() {
+        assert_eq!(score("xyz", "src/backend/buffer.rs"), None);
+        // In order only
+        assert_eq!(score("rb", "br"), None);
+        assert_eq!(score("aa", "a"), None);
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let m = score("", "anything").unwrap();
+        assert_eq!(m.score, 0);
+        assert!(m.positions.is_empty());
+    }
+
+    #[test]
+    fn positions_point_at_the_query() {
+        for (query, text) in [
+            ("bufrs", "src/backend/buffer.rs"),
+            ("sbb", "src/backend/buffer.rs"),
+            ("aaa", "abababa"),
+            ("ed", "src/ui/editor.rs"),
+        ] {
+            let m = score(query, text).unwrap();
+            let chars: Vec<char> = text.chars().collect();
+            assert_eq!(m.positions.len(), query.chars().count());
+            assert!(m.positions.windows(2).all(|w| w[0] < w[1]), "{:?}", m);
+            let matched: String = m.positions.iter().map(|i| chars[*i]).collect();
+            assert_eq!(matched, query);
+        }
+    }
+
+    #[test]
+    fn prefers_consecutive_and_boundaries() {
+        let m = score("buf", "src/backend/buffer.rs").unwrap();
+        assert_eq!(m.positions, vec![12, 13, 14]);
+        let m = score("fb", "foo_bar fxb").unwrap();
+        assert_eq!(m.positions, vec![0, 4]);
+    }
+
+    #[test]
+    fn reused_matcher_scores_the_same() {
+        let texts = [
+            "src/backend/buffer.rs",
+            "b",
+            "src/ui/editor.rs",
+            "Cargo.toml",
+            "benches/ui/frames.rs",
+        ];
+        let mut matcher = Matcher::new("bur");
+        for _ in 0..2 {
+            for text in &texts {
+                assert_eq!(matcher.score(text), score("bur", text), "{}", text);
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+// Files under a project dir, found once by a background thread and
+// kept up to date by watching every dir for changes. The finder reads
+// whatever is there so far instead of walking the tree on every key.
+use ignore::WalkBuilder;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+// Files added to the index at once while walking
+const BATCH: usize = 256;
+
+// One index per dir and hidden/ignored setting, for the whole session
+static INDEXES: Mutex<Vec<Arc<Index>>> = Mutex::new(Vec::new());
+
+pub struct Index {
+    dir: PathBuf,
+    all: bool,
+    files: Mutex<Files>,
+}
+
+pub struct Files {
+    pub paths: BTreeSet<PathBuf>,
+    // The first walk isn't done yet, `paths` is still growing
+    pub indexing: bool,
+    // Bumped on every change, so readers can tell when to look again
+    pub version: u64,
+}
+
+impl Files {
+    fn set_indexing(&mut self, indexing: bool) {
+        self.indexing = indexing;
+        self.version += 1;
+    }
+}
+
+// The index of `dir`, started in the background the first time
+pub fn get(dir: &Path, all: bool) -> Arc<Index> {
+    let mut indexes = INDEXES.lock().unwrap();
+    if let Some(i) = indexes.iter().find(|i| i.dir == dir && i.all == all) {
+        return i.clone();
+    }
+
+    let index = Arc::new(Index {
+        dir: dir.to_path_buf(),
+        all,
+        files: Mutex::new(Files {
+            paths: BTreeSet::new(),
+            indexing: true,
+            version: 0,
+        }),
+    });
+    let i = index.clone();
+    thread::spawn(move || i.run());
+    indexes.push(index.clone());
+    index
+}
+
+impl Index {
+    pub fn files(&self) -> MutexGuard<'_, Files> {
+        self.files.lock().unwrap()
+    }
+
+    fn run(&self) {
+        let (tx, rx) = mpsc::channel();
+        // Without a watcher the files are still found, just not kept up to date
+        let mut watcher = notify::recommended_watcher(tx).ok();
+        self.add_dir(&self.dir, &mut watcher);
+        self.files().set_indexing(false);
+
+        for event in rx.iter().filter_map(|e| e.ok()) {
+            self.update(event, &mut watcher);
+        }
+    }
+
+    // Index everything under `p`, watching each dir on the way
+    fn add_dir(&self, p: &Path, watcher: &mut Option<RecommendedWatcher>) {
+        watch(watcher, p);
+        let mut batch = Vec::new();
+        for path in walk(p, self.all) {
+            if path.is_dir() {
+                watch(watcher, &path);
+                continue;
+            }
+            batch.push(path);
+            if batch.len() >= BATCH {
+                self.add(batch.drain(..));
+            }
+        }
+        self.add(batch);
+    }
+
+    fn add<I: IntoIterator<Item = PathBuf>>(&self, paths: I) {
+        let mut files = self.files();
+        files.paths.extend(paths);
+        files.version += 1;
+    }
+
+    fn update(&self, event: Event, watcher: &mut Option<RecommendedWatcher>) {
+        let paths = &event.paths;
+
+        // Changed ignore rules can hide or show anything, start over
+        if !matches!(event.kind, EventKind::Access(_)) && paths.iter().any(|p| is_ignore_file(p)) {
+            let mut files = self.files();
+            files.paths.clear();
+            files.set_indexing(true);
+            drop(files);
+            self.add_dir(&self.dir, watcher);
+            self.files().set_indexing(false);
+            return;
+        }
+
+        match event.kind {
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                paths.iter().for_each(|p| self.created(p, watcher));
+            }
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                paths.iter().for_each(|p| self.removed(p));
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                self.removed(&paths[0]);
+                self.created(&paths[1], watcher);
+            }
+            _ => (),
+        }
+    }
+
+    fn created(&self, p: &Path, watcher: &mut Option<RecommendedWatcher>) {
+        let parent = match p.parent() {
+            Some(d) => d,
+            None => return,
+        };
+        // Let the walker decide, so ignore files and hidden files count
+        let kept = builder(parent, self.all)
+            .max_depth(Some(1))
+            .build()
+            .filter_map(|e| e.ok())
+            .any(|e| e.path() == p);
+        if !kept {
+            return;
+        }
+
+        if p.is_dir() {
+            self.add_dir(p, watcher);
+        } else {
+            self.add(Some(p.to_path_buf()));
+        }
+    }
+
+    // Drop `p`, and everything under it when it was a dir
+    fn removed(&self, p: &Path) {
+        let mut files = self.files();
+        let gone: Vec<PathBuf> = files
+            .paths
+            .range(p.to_path_buf()..)
+            .take_while(|f| f.starts_with(p))
+            .cloned()
+            .collect();
+        if gone.is_empty() {
+            return;
+        }
+        for f in gone {
+            files.paths.remove(&f);
+        }
+        files.version += 1;
+    }
+}
+
+fn watch(watcher: &mut Option<RecommendedWatcher>, p: &Path) {
+    if let Some(w) = watcher {
+        // Running out of watches only means missing some updates
+        let _ = w.watch(p, RecursiveMode::NonRecursive);
+    }
+}
+
+// `.git` isn't watched, so only these are noticed
+fn is_ignore_file(p: &Path) -> bool {
+    matches!(
+        p.file_name().and_then(|n| n.to_str()),
+        Some(".gitignore") | Some(".ignore")
+    )
+}
+
+fn builder(p: &Path, all: bool) -> WalkBuilder {
+    let mut b = WalkBuilder::new(p);
+    b.standard_filters(!all)
+        // .gitignore counts outside of a repo too
+        .require_git(false)
+        .filter_entry(|e| e.file_name() != ".git");
+    b
+}
+
+// Everything under `p`, leaving out hidden files and whatever
+// .gitignore, .ignore, .git/info/exclude and the global git excludes
+// file ignore. With `all` those are in too, only `.git` never is.
+pub fn walk(p: &Path, all: bool) -> impl Iterator<Item = PathBuf> {
+    builder(p, all)
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.depth() > 0)
+        .map(|e| e.into_path())
+}
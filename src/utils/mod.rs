@@ -0,0 +1,6 @@
+// Things the components share
+//  - Fuzzy matching
+//  - The project file index
+
+pub mod fuzzy;
+pub mod index;